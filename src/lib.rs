@@ -1,37 +1,132 @@
-use std::path::Path;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
+use base64::prelude::*;
+use image::{
+    ExtendedColorType, ImageEncoder,
+    codecs::{
+        jpeg::JpegEncoder,
+        png::{CompressionType, FilterType, PngEncoder},
+        webp::WebPEncoder,
+    },
+};
+use js_sys::{Array, Function, Object, Reflect, Uint8Array};
+use serde_json::{Value, json};
+use tiny_skia::Pixmap;
 use typst::{
     Library, LibraryExt, World,
-    diag::SourceDiagnostic,
+    diag::{Severity, SourceDiagnostic, SourceResult},
     ecow::EcoVec,
-    foundations::{Bytes, Datetime},
-    syntax::{FileId, Source},
+    foundations::{Bytes, Datetime, Dict, IntoValue, Label, NativeElement, Selector},
+    layout::{Abs, FrameItem, PageRanges, Point},
+    math::EquationElem,
+    model::{
+        BibliographyElem, CiteElem, FigureElem, FootnoteElem, HeadingElem, Numbering, RefElem,
+        TableElem,
+    },
+    syntax::{
+        FileId, Source, Span,
+        package::{PackageSpec, PackageVersion},
+    },
     text::{Font, FontBook},
-    utils::LazyHash,
+    utils::{LazyHash, PicoStr, hash128},
+    visualize::{ColorSpace, ImageElem, Paint},
 };
-use typst_kit::{datetime::Time, files::FileStore, fonts::FontStore};
-use typst_layout::PagedDocument;
-use typst_pdf::PdfOptions;
+use typst_kit::{datetime::Time, files::FileStore};
+use typst_layout::{Page, PagedDocument};
+use typst_pdf::{PdfOptions, PdfStandard, PdfStandards, Timestamp};
 use wasm_bindgen::prelude::*;
 
 mod fs {
     use std::{
         collections::HashMap,
         path::{Path, PathBuf},
-        sync::Mutex,
+        sync::{
+            LazyLock, Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
     };
 
     use typst::{
         diag::FileError,
         foundations::Bytes,
-        syntax::{FileId, RootedPath, VirtualPath, VirtualRoot},
+        syntax::{FileId, RootedPath, VirtualPath, VirtualRoot, package::PackageSpec},
     };
     use typst_kit::files::{FileLoader, FsRoot};
 
+    /// Package files shared by all instances
+    ///
+    /// wasm runs everything on one thread, so the lock is never contended,
+    /// it only makes the store usable from a `static`.
+    static PACKAGES: LazyLock<Mutex<HashMap<FileId, Bytes>>> = LazyLock::new(Default::default);
+
+    /// Bumped whenever `PACKAGES` changes, so instances know to drop their caches
+    static PACKAGES_VERSION: AtomicUsize = AtomicUsize::new(0);
+
+    /// Adds a file to the shared package store, returns `None` if the path is invalid
+    pub fn stage_package(spec: PackageSpec, path: &Path, data: Bytes) -> Option<()> {
+        let vpath = VirtualPath::virtualize(Path::new(""), rootless(path)).ok()?;
+        let id = RootedPath::new(VirtualRoot::Package(spec), vpath).intern();
+
+        PACKAGES.lock().ok()?.insert(id, data);
+        PACKAGES_VERSION.fetch_add(1, Ordering::Relaxed);
+
+        Some(())
+    }
+
+    /// Whether a package has been staged, judged by its `typst.toml`
+    pub fn has_package(spec: &PackageSpec) -> bool {
+        let Ok(manifest) = VirtualPath::virtualize(Path::new(""), Path::new("typst.toml")) else {
+            return false;
+        };
+        let id = RootedPath::new(VirtualRoot::Package(spec.clone()), manifest).intern();
+
+        PACKAGES
+            .lock()
+            .is_ok_and(|packages| packages.contains_key(&id))
+    }
+
+    pub fn packages_version() -> usize {
+        PACKAGES_VERSION.load(Ordering::Relaxed)
+    }
+
     pub struct FS {
+        /// The main file as seen by typst, relative to `root`
         pub main: FileId,
+        /// The generated file that applies the prelude and includes `main`
+        pub prelude: FileId,
+        main_path: PathBuf,
+        root: PathBuf,
         project_path: PathBuf,
         files: Mutex<HashMap<FileId, Bytes>>,
+        urls: HashMap<String, Bytes>,
+        /// URLs that were requested but not attached
+        missing_urls: Mutex<Vec<String>>,
+    }
+
+    /// The URL a path refers to, if it contains an `http:` or `https:` component.
+    ///
+    /// Typst treats `image("https://example.com/a.png")` as a relative path,
+    /// which it normalizes to e.g. `/https:/example.com/a.png`.
+    pub fn url_of(path: &str) -> Option<String> {
+        ["https:/", "http:/"].iter().find_map(|scheme| {
+            let start = path
+                .match_indices(scheme)
+                .find(|(i, _)| *i == 0 || path[..*i].ends_with('/'))?
+                .0;
+            let rest = path[start + scheme.len()..].trim_start_matches('/');
+
+            Some(format!("{scheme}/{rest}"))
+        })
+    }
+
+    /// Strips the leading `/`, so that `/main.typ` and `main.typ` are the same file
+    fn rootless(path: &Path) -> &Path {
+        path.strip_prefix("/").unwrap_or(path)
     }
 
     fn str_to_rooted(root: &Path, path: &Path) -> RootedPath {
@@ -46,16 +141,89 @@ mod fs {
             let project_path = project.path().to_path_buf();
             let path = PathBuf::from("main.typ");
             let main = str_to_rooted(&project_path, &path).intern();
+            let prelude = str_to_rooted(&project_path, Path::new("typ-js-prelude.typ")).intern();
 
             Self {
                 main,
+                prelude,
+                main_path: path,
+                root: PathBuf::new(),
                 project_path,
                 files: Mutex::new(HashMap::new()),
+                urls: HashMap::new(),
+                missing_urls: Mutex::new(Vec::new()),
+            }
+        }
+
+        pub fn main_path(&self) -> &Path {
+            &self.main_path
+        }
+
+        /// Returns `None` and keeps the main file if the path leaves the project root
+        pub fn set_main(&mut self, path: &Path) -> Option<()> {
+            let path = rootless(path);
+            let relative = path.strip_prefix(&self.root).unwrap_or(path);
+            VirtualPath::virtualize(&self.project_path, relative).ok()?;
+
+            self.main_path = path.to_path_buf();
+            self.update_main();
+            Some(())
+        }
+
+        /// Whether a path given like in `write` stays inside the project root
+        pub fn is_valid(&self, path: &Path) -> bool {
+            VirtualPath::virtualize(&self.project_path, rootless(path)).is_ok()
+        }
+
+        /// Returns `None` and keeps the root if it, or the main file inside it,
+        /// leaves the project root
+        pub fn set_root(&mut self, root: &Path) -> Option<()> {
+            let root = rootless(root);
+            if !root.as_os_str().is_empty() {
+                VirtualPath::virtualize(&self.project_path, root).ok()?;
+            }
+            let main = self.main_path.strip_prefix(root).unwrap_or(&self.main_path);
+            VirtualPath::virtualize(&self.project_path, main).ok()?;
+
+            self.root = root.to_path_buf();
+            self.update_main();
+            Some(())
+        }
+
+        fn update_main(&mut self) {
+            let path = self
+                .main_path
+                .strip_prefix(&self.root)
+                .unwrap_or(&self.main_path);
+
+            self.main = str_to_rooted(&self.project_path, path).intern();
+        }
+
+        /// The id typst uses for a file, given its path in the format of `list`
+        pub fn id(&self, path: &Path) -> FileId {
+            let path = rootless(path);
+            let path = path.strip_prefix(&self.root).unwrap_or(path);
+
+            str_to_rooted(&self.project_path, path).intern()
+        }
+
+        /// The path of a file as seen by typst, in the format of `list`
+        ///
+        /// Package files aren't in the project, they're labeled with their package instead,
+        /// e.g. `@preview/example:0.1.0/lib.typ`.
+        pub fn path(&self, id: FileId) -> String {
+            if let VirtualRoot::Package(spec) = id.root() {
+                return format!("{spec}/{}", id.vpath().get_without_slash());
             }
+
+            self.root
+                .join(id.vpath().get_without_slash())
+                .to_string_lossy()
+                .into_owned()
         }
 
         fn find(&self, path: &Path) -> FileId {
-            let rooted = str_to_rooted(&self.project_path, path);
+            let rooted = str_to_rooted(&self.project_path, rootless(path));
 
             FileId::new(rooted)
         }
@@ -76,6 +244,48 @@ mod fs {
                 .collect()
         }
 
+        pub fn root(&self) -> &Path {
+            &self.root
+        }
+
+        pub fn read(&self, path: &Path) -> Option<Bytes> {
+            self.files.lock().unwrap().get(&self.find(path)).cloned()
+        }
+
+        /// All files with their rootless paths
+        pub fn entries(&self) -> Vec<(String, Bytes)> {
+            self.files
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(id, data)| (id.vpath().get_without_slash().to_string(), data.clone()))
+                .collect()
+        }
+
+        /// All attached URLs with their contents, sorted by URL
+        pub fn urls(&self) -> Vec<(&str, &Bytes)> {
+            let mut urls: Vec<_> = self
+                .urls
+                .iter()
+                .map(|(url, data)| (url.as_str(), data))
+                .collect();
+            urls.sort_by_key(|(url, _)| *url);
+            urls
+        }
+
+        pub fn attach_url(&mut self, url: &str, data: Bytes) {
+            let url = url_of(url).unwrap_or_else(|| url.into());
+            self.urls.insert(url, data);
+        }
+
+        pub fn missing_urls(&self) -> Vec<String> {
+            self.missing_urls.lock().unwrap().clone()
+        }
+
+        pub fn clear_missing_urls(&self) {
+            self.missing_urls.lock().unwrap().clear();
+        }
+
         pub fn write(&mut self, path: &Path, data: Bytes) {
             let Ok(mut fs) = self.files.lock() else {
                 return;
@@ -85,165 +295,5180 @@ mod fs {
         }
     }
 
+    impl Clone for FS {
+        /// Copies the file map, the file contents themselves are shared
+        fn clone(&self) -> Self {
+            Self {
+                main: self.main,
+                prelude: self.prelude,
+                main_path: self.main_path.clone(),
+                root: self.root.clone(),
+                project_path: self.project_path.clone(),
+                files: Mutex::new(self.files.lock().unwrap().clone()),
+                urls: self.urls.clone(),
+                missing_urls: Mutex::new(self.missing_urls()),
+            }
+        }
+    }
+
+    /// Whether a file belongs to the project, as opposed to a package
+    pub fn is_project(id: FileId) -> bool {
+        matches!(id.root(), VirtualRoot::Project)
+    }
+
     impl FileLoader for FS {
         fn load(&self, id: FileId) -> typst::diag::FileResult<typst::foundations::Bytes> {
+            if !is_project(id) {
+                let packages = PACKAGES.lock().map_err(|_| FileError::AccessDenied)?;
+                return match packages.get(&id) {
+                    Some(bytes) => Ok(bytes.clone()),
+                    None => Err(FileError::NotFound(id.vpath().get_with_slash().into())),
+                };
+            }
+
+            if let Some(url) = url_of(id.vpath().get_without_slash()) {
+                if let Some(bytes) = self.urls.get(&url) {
+                    return Ok(bytes.clone());
+                }
+
+                let mut missing = self.missing_urls.lock().unwrap();
+                if !missing.contains(&url) {
+                    missing.push(url.clone());
+                }
+                return Err(FileError::Other(Some(
+                    format!("{url} is not attached").into(),
+                )));
+            }
+
             let store = self.files.lock().map_err(|_| FileError::AccessDenied)?;
+            let path = self.root.join(id.vpath().get_without_slash());
 
-            match store.get(&id) {
+            match store.get(&self.find(&path)) {
                 Some(bytes) => Ok(bytes.clone()),
                 None => Err(FileError::NotFound(id.vpath().get_with_slash().into())),
             }
         }
     }
-}
 
-#[wasm_bindgen]
-pub struct TypJs {
-    lib: LazyHash<Library>,
-    fonts: FontStore,
-    files: FileStore<fs::FS>,
-    errors: EcoVec<SourceDiagnostic>,
-    now: Time,
-}
+    #[cfg(test)]
+    mod tests {
+        use std::path::Path;
 
-#[wasm_bindgen]
-impl TypJs {
-    pub fn new() -> Self {
-        let mut fonts = FontStore::new();
-        fonts.extend(typst_kit::fonts::embedded());
+        use typst::foundations::Bytes;
 
-        let mut files = FileStore::new(fs::FS::new());
-        files
-            .loader_mut()
-            .write(Path::new("/main.typ"), Bytes::new("Hello"));
+        use super::FS;
 
-        Self {
-            lib: LazyHash::new(Library::default()), // stdlib
-            fonts,
-            files,
-            errors: EcoVec::new(),
-            now: Time::system(),
+        #[test]
+        fn leading_slash_is_optional() {
+            let mut fs = FS::new();
+            fs.write(Path::new("/lib/util.typ"), Bytes::new("#let x = 1"));
+
+            assert!(fs.read(Path::new("lib/util.typ")).is_some());
+            assert_eq!(
+                fs.id(Path::new("/lib/util.typ")),
+                fs.id(Path::new("lib/util.typ"))
+            );
         }
-    }
 
-    /// Deletes a given file
-    pub fn delete(&mut self, path: &str) {
-        self.files.loader_mut().delete(Path::new(path));
+        #[test]
+        fn ids_are_relative_to_the_root() {
+            let mut fs = FS::new();
+            let id = fs.id(Path::new("lib/util.typ"));
+            assert_eq!(id.vpath().get_without_slash(), "lib/util.typ");
 
-        self.files.reset();
+            fs.set_root(Path::new("/proj"));
+            let id = fs.id(Path::new("proj/lib/util.typ"));
+            assert_eq!(id.vpath().get_without_slash(), "lib/util.typ");
+            assert_eq!(fs.path(id), "proj/lib/util.typ");
+        }
     }
+}
 
-    /// Returns the paths to all files available to the compiler,
-    /// including `main.typ`.
-    ///
-    /// Paths do *NOT* start with `/`.
-    pub fn list(&self) -> Vec<String> {
-        self.files.loader().list()
-    }
+mod zip {
+    use std::{
+        io::{Read, Write},
+        path::{Component, Path, PathBuf},
+    };
 
-    /// Returns a list of errors if the last compilation failed or warnings if it finished successfully
-    pub fn errors(&self) -> Vec<String> {
-        self.errors
-            .iter()
-            .map(|err| {
-                format!(
-                    "SPAN: {:?} ||| MSG: {} ||| HINT: {}",
-                    err.span,
-                    err.message.clone(),
-                    err.hints
-                        .iter()
-                        .map(|spanned| spanned.v.clone())
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                )
-            })
-            .collect()
+    use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+
+    const LOCAL_HEADER: u32 = 0x04034b50;
+    const CENTRAL_HEADER: u32 = 0x02014b50;
+    const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+    const STORED: u16 = 0;
+    const DEFLATED: u16 = 8;
+    /// The version of the format needed to extract deflated entries
+    const VERSION: u16 = 20;
+    /// Marks names as UTF-8
+    const UTF8_NAME: u16 = 1 << 11;
+    /// 1980-01-01, the earliest date the format can store
+    const DOS_DATE: u16 = 0x21;
+
+    pub struct Entry {
+        pub name: String,
+        flags: u16,
+        method: u16,
+        crc: u32,
+        compressed_size: usize,
+        size: usize,
+        header: usize,
     }
 
-    /// Sets the text content of a given `.typ` file.
-    ///
-    /// The root file is called `main.typ`
-    pub fn write(&mut self, path: &str, text: String) {
-        self.files
-            .loader_mut()
-            .write(Path::new(path), Bytes::new(text));
+    impl Entry {
+        pub fn is_file(&self) -> bool {
+            !self.name.ends_with('/')
+        }
 
-        self.files.reset();
+        /// The path of the entry, unless it leaves the archive root
+        pub fn enclosed_name(&self) -> Option<PathBuf> {
+            if self.name.contains('\0') {
+                return None;
+            }
+
+            let mut path = PathBuf::new();
+            for component in Path::new(&self.name).components() {
+                match component {
+                    Component::Normal(part) => path.push(part),
+                    Component::CurDir => {}
+                    Component::ParentDir if path.pop() => {}
+                    _ => return None,
+                }
+            }
+
+            Some(path)
+        }
     }
 
-    /// Adds a binary file (image, font, etc.)
-    pub fn attach(&mut self, path: &str, data: Vec<u8>) {
-        self.files
-            .loader_mut()
-            .write(Path::new(path), Bytes::new(data));
+    /// A zip archive in memory, with stored or deflated entries.
+    /// Zip64, encryption and archives split across disks aren't supported.
+    pub struct Archive {
+        data: Vec<u8>,
+        entries: Vec<Entry>,
     }
 
-    /// Outputs an SVG string with the rendered document
-    ///
-    /// If there are compile errors, sets the `errors` field and returns empty string
-    pub fn svg(&mut self) -> String {
-        let compiled = typst::compile::<PagedDocument>(self);
+    impl Archive {
+        pub fn new(data: Vec<u8>) -> Result<Self, String> {
+            // The end of central directory record is 22 bytes, followed by a comment of up to 64 KiB
+            let end = (0..=data.len().saturating_sub(22))
+                .rev()
+                .take(0x10000)
+                .find(|&at| u32_at(&data, at) == Ok(END_OF_CENTRAL_DIRECTORY))
+                .ok_or("not a zip archive")?;
 
-        match compiled.output {
-            Err(errors) => {
-                self.errors = errors;
-                String::new()
+            let count = u16_at(&data, end + 10)?;
+            let mut at = u32_at(&data, end + 16)? as usize;
+            if count == u16::MAX || at == u32::MAX as usize {
+                return Err("zip64 archives aren't supported".into());
             }
-            Ok(doc) => {
-                self.errors = compiled.warnings;
-                doc.pages()
-                    .iter()
-                    .map(|page| typst_svg::svg(page))
-                    .collect()
+
+            let mut entries = Vec::new();
+            for _ in 0..count {
+                if at > data.len() || u32_at(&data, at)? != CENTRAL_HEADER {
+                    return Err("broken central directory".into());
+                }
+
+                let name_len = u16_at(&data, at + 28)? as usize;
+                let extra_len = u16_at(&data, at + 30)? as usize;
+                let comment_len = u16_at(&data, at + 32)? as usize;
+                let name = data.get(at + 46..at + 46 + name_len).ok_or(TRUNCATED)?;
+                let entry = Entry {
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    flags: u16_at(&data, at + 8)?,
+                    method: u16_at(&data, at + 10)?,
+                    crc: u32_at(&data, at + 16)?,
+                    compressed_size: u32_at(&data, at + 20)? as usize,
+                    size: u32_at(&data, at + 24)? as usize,
+                    header: u32_at(&data, at + 42)? as usize,
+                };
+                if [entry.compressed_size, entry.size, entry.header].contains(&(u32::MAX as usize))
+                {
+                    return Err("zip64 archives aren't supported".into());
+                }
+
+                entries.push(entry);
+                at += 46 + name_len + extra_len + comment_len;
+            }
+
+            Ok(Self { data, entries })
+        }
+
+        pub fn entries(&self) -> &[Entry] {
+            &self.entries
+        }
+
+        /// The uncompressed contents of an entry, checked against its CRC-32
+        pub fn read(&self, entry: &Entry) -> Result<Vec<u8>, String> {
+            if entry.flags & 1 != 0 {
+                return Err("encrypted entries aren't supported".into());
+            }
+
+            let at = entry.header;
+            if at > self.data.len() || u32_at(&self.data, at)? != LOCAL_HEADER {
+                return Err("missing local file header".into());
+            }
+            let name_len = u16_at(&self.data, at + 26)? as usize;
+            let extra_len = u16_at(&self.data, at + 28)? as usize;
+            let start = at + 30 + name_len + extra_len;
+            let raw = start
+                .checked_add(entry.compressed_size)
+                .and_then(|end| self.data.get(start..end))
+                .ok_or(TRUNCATED)?;
+
+            let data = match entry.method {
+                STORED => raw.to_vec(),
+                DEFLATED => {
+                    // One byte more than expected is enough to tell the size is wrong
+                    let mut data = Vec::new();
+                    DeflateDecoder::new(raw)
+                        .take(entry.size as u64 + 1)
+                        .read_to_end(&mut data)
+                        .map_err(|err| err.to_string())?;
+                    data
+                }
+                method => return Err(format!("compression method {method} isn't supported")),
+            };
+
+            if data.len() != entry.size || crc32fast::hash(&data) != entry.crc {
+                return Err("entry is corrupted".into());
             }
+
+            Ok(data)
         }
     }
 
-    /// Outputs a PDF with the rendered document as a UInt8Array
-    ///
-    /// If there are compile errors, sets the `errors` field and returns empty array
-    pub fn pdf(&mut self) -> Vec<u8> {
-        let compiled = typst::compile::<PagedDocument>(self);
+    /// Writes an archive of deflated entries
+    pub struct Writer {
+        data: Vec<u8>,
+        central: Vec<u8>,
+        count: u16,
+    }
 
-        match compiled.output {
-            Err(errors) => {
-                self.errors = errors;
-                Vec::new()
+    impl Writer {
+        pub fn new() -> Self {
+            Self {
+                data: Vec::new(),
+                central: Vec::new(),
+                count: 0,
             }
-            Ok(doc) => {
-                self.errors = compiled.warnings;
-                typst_pdf::pdf(&doc, &PdfOptions::default()).unwrap_or_default()
+        }
+
+        /// Adds a file, `None` if the archive would need zip64
+        pub fn add(&mut self, name: &str, contents: &[u8]) -> Option<()> {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(contents).ok()?;
+            let compressed = encoder.finish().ok()?;
+
+            let header = u32::try_from(self.data.len()).ok()?;
+            let compressed_size = u32::try_from(compressed.len()).ok()?;
+            let size = u32::try_from(contents.len()).ok()?;
+            let name_len = u16::try_from(name.len()).ok()?;
+            self.count = self
+                .count
+                .checked_add(1)
+                .filter(|count| *count < u16::MAX)?;
+
+            // The fields the local and central headers share, from the version needed
+            // to extract up to the length of the extra field, which is empty
+            let mut fields = Vec::new();
+            for field in [VERSION, UTF8_NAME, DEFLATED, 0, DOS_DATE] {
+                fields.extend(field.to_le_bytes());
             }
+            fields.extend(crc32fast::hash(contents).to_le_bytes());
+            fields.extend(compressed_size.to_le_bytes());
+            fields.extend(size.to_le_bytes());
+            fields.extend(name_len.to_le_bytes());
+            fields.extend(0u16.to_le_bytes());
+
+            self.data.extend(LOCAL_HEADER.to_le_bytes());
+            self.data.extend(&fields);
+            self.data.extend(name.as_bytes());
+            self.data.extend(compressed);
+
+            self.central.extend(CENTRAL_HEADER.to_le_bytes());
+            self.central.extend(VERSION.to_le_bytes());
+            self.central.extend(&fields);
+            // Comment length, disk number, internal and external attributes
+            self.central.extend([0; 10]);
+            self.central.extend(header.to_le_bytes());
+            self.central.extend(name.as_bytes());
+
+            Some(())
+        }
+
+        pub fn finish(mut self) -> Option<Vec<u8>> {
+            let start = u32::try_from(self.data.len()).ok()?;
+            let size = u32::try_from(self.central.len()).ok()?;
+
+            self.data.append(&mut self.central);
+            self.data.extend(END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+            // This disk and the disk the central directory starts on
+            self.data.extend([0; 4]);
+            self.data.extend(self.count.to_le_bytes());
+            self.data.extend(self.count.to_le_bytes());
+            self.data.extend(size.to_le_bytes());
+            self.data.extend(start.to_le_bytes());
+            // Comment length
+            self.data.extend([0; 2]);
+
+            Some(self.data)
         }
     }
+
+    const TRUNCATED: &str = "archive is truncated";
+
+    fn u16_at(data: &[u8], at: usize) -> Result<u16, String> {
+        let bytes = data.get(at..at + 2).ok_or(TRUNCATED)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> Result<u32, String> {
+        let bytes = data.get(at..at + 4).ok_or(TRUNCATED)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
 }
 
-impl World for TypJs {
-    fn library(&self) -> &LazyHash<Library> {
-        &self.lib
+mod frames {
+    use typst::{
+        foundations::{Content, Label},
+        introspection::Tag,
+        layout::{Abs, Frame, FrameItem, Point, Size, Transform},
+        syntax::Span,
+        visualize::Paint,
+    };
+
+    /// An axis-aligned rectangle in page coordinates
+    #[derive(Clone, Copy)]
+    pub struct Rect {
+        pub min: Point,
+        pub max: Point,
     }
 
-    fn book(&self) -> &LazyHash<FontBook> {
-        &self.fonts.book()
+    impl Rect {
+        pub fn size(&self) -> Size {
+            Size::new(self.max.x - self.min.x, self.max.y - self.min.y)
+        }
+
+        /// Grows the rectangle by `by` on every side
+        pub fn pad(self, by: Abs) -> Rect {
+            Rect {
+                min: self.min - Point::splat(by),
+                max: self.max + Point::splat(by),
+            }
+        }
+
+        pub fn union(self, other: Rect) -> Rect {
+            Rect {
+                min: Point::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y)),
+                max: Point::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y)),
+            }
+        }
+
+        /// The distance from a point to the rectangle in points, 0 if it's inside
+        pub fn distance(&self, point: Point) -> f64 {
+            let dx = (self.min.x - point.x)
+                .max(point.x - self.max.x)
+                .max(Abs::zero());
+            let dy = (self.min.y - point.y)
+                .max(point.y - self.max.y)
+                .max(Abs::zero());
+
+            dx.to_pt().hypot(dy.to_pt())
+        }
+
+        /// The smallest rectangle containing the given corners of a local rectangle
+        fn transformed(min: Point, max: Point, ts: Transform) -> Rect {
+            let corners = [min, Point::new(max.x, min.y), Point::new(min.x, max.y), max]
+                .map(|corner| corner.transform(ts));
+
+            corners[1..].iter().fold(
+                Rect {
+                    min: corners[0],
+                    max: corners[0],
+                },
+                |rect, &corner| {
+                    rect.union(Rect {
+                        min: corner,
+                        max: corner,
+                    })
+                },
+            )
+        }
     }
 
-    fn main(&self) -> FileId {
-        self.files.loader().main
+    /// The area covered by an item drawn with the given transform,
+    /// ignoring stroke thickness. Groups are covered by their items.
+    pub fn bounds(ts: Transform, item: &FrameItem) -> Option<Rect> {
+        let (min, max) = match item {
+            FrameItem::Text(text) => {
+                let metrics = text.font.metrics();
+                (
+                    Point::new(Abs::zero(), -metrics.ascender.at(text.size)),
+                    Point::new(text.width(), -metrics.descender.at(text.size)),
+                )
+            }
+            FrameItem::Shape(shape, _) => (Point::zero(), shape.geometry.bbox_size().to_point()),
+            FrameItem::Image(_, size, _) => (Point::zero(), size.to_point()),
+            _ => return None,
+        };
+
+        Some(Rect::transformed(min, max, ts))
     }
 
-    fn source(&self, id: FileId) -> typst::diag::FileResult<Source> {
-        self.files.source(id)
+    /// The area covered by all items of a frame
+    pub fn content_bounds(frame: &Frame) -> Option<Rect> {
+        let mut rect: Option<Rect> = None;
+        walk(frame, &mut |ts, item| {
+            if let Some(item) = bounds(ts, item) {
+                rect = Some(rect.map_or(item, |rect| rect.union(item)));
+            }
+        });
+
+        rect
     }
 
-    fn file(&self, id: FileId) -> typst::diag::FileResult<Bytes> {
-        self.files.file(id)
+    /// The area covered by the first element with the given label
+    pub fn element_bounds(frame: &Frame, label: Label) -> Option<Rect> {
+        let mut active = None;
+        let mut done = false;
+        let mut rect: Option<Rect> = None;
+
+        walk(frame, &mut |ts, item| {
+            if done {
+                return;
+            }
+
+            match (item, active) {
+                (FrameItem::Tag(tag @ Tag::Start(content, ..)), None)
+                    if content.label() == Some(label) =>
+                {
+                    active = Some(tag.location());
+                }
+                (FrameItem::Tag(tag @ Tag::End(..)), Some(location))
+                    if tag.location() == location =>
+                {
+                    done = true;
+                }
+                (_, Some(_)) => {
+                    if let Some(item) = bounds(ts, item) {
+                        rect = Some(rect.map_or(item, |rect| rect.union(item)));
+                    }
+                }
+                _ => {}
+            }
+        });
+
+        rect
     }
 
-    fn font(&self, index: usize) -> Option<Font> {
-        self.fonts.font(index)
+    /// The span of the glyph, shape or image closest to `point`, with the offset into it
+    ///
+    /// Glyphs are measured by their advance between ascender and descender.
+    /// Items with detached spans are skipped.
+    pub fn nearest_span(frame: &Frame, point: Point) -> Option<(Span, u16)> {
+        let mut nearest: Option<(f64, (Span, u16))> = None;
+        let mut consider = |rect: Rect, span: (Span, u16)| {
+            let distance = rect.distance(point);
+            if !span.0.is_detached() && nearest.is_none_or(|(best, _)| distance < best) {
+                nearest = Some((distance, span));
+            }
+        };
+
+        walk(frame, &mut |ts, item| match item {
+            FrameItem::Text(text) => {
+                let metrics = text.font.metrics();
+                let (top, bottom) = (
+                    -metrics.ascender.at(text.size),
+                    -metrics.descender.at(text.size),
+                );
+
+                let mut x = Abs::zero();
+                for glyph in &text.glyphs {
+                    let start = x + glyph.x_offset.at(text.size);
+                    x += glyph.x_advance.at(text.size);
+                    let rect = Rect::transformed(
+                        Point::new(start, top),
+                        Point::new(start.max(x), bottom),
+                        ts,
+                    );
+                    consider(rect, glyph.span);
+                }
+            }
+            FrameItem::Shape(_, span) | FrameItem::Image(_, _, span) => {
+                if let Some(rect) = bounds(ts, item) {
+                    consider(rect, (*span, 0));
+                }
+            }
+            _ => {}
+        });
+
+        nearest.map(|(_, span)| span)
     }
 
-    fn today(&self, offset: Option<typst::foundations::Duration>) -> Option<Datetime> {
-        self.now.today(offset)
+    /// The elements whose content covers `point`, innermost first, with the area they cover
+    ///
+    /// Only elements typst tracks in the frames are found, e.g. headings, figures,
+    /// links and anything with a label.
+    pub fn elements_at(frame: &Frame, point: Point) -> Vec<(Content, Rect)> {
+        let mut open: Vec<(Content, Option<Rect>)> = Vec::new();
+        let mut found = Vec::new();
+
+        walk(frame, &mut |ts, item| match item {
+            FrameItem::Tag(Tag::Start(content, ..)) => open.push((content.clone(), None)),
+            FrameItem::Tag(tag @ Tag::End(..)) => {
+                let position = open
+                    .iter()
+                    .rposition(|(content, _)| content.location() == Some(tag.location()));
+                if let Some(position) = position {
+                    let (content, rect) = open.remove(position);
+                    if let Some(rect) = rect.filter(|rect| rect.distance(point) == 0.0) {
+                        found.push((content, rect));
+                    }
+                }
+            }
+            _ => {
+                if let Some(item) = bounds(ts, item) {
+                    for (_, rect) in &mut open {
+                        *rect = Some(rect.map_or(item, |rect| rect.union(item)));
+                    }
+                }
+            }
+        });
+
+        found
+    }
+
+    /// The text of a frame in layout order, with a line break wherever the baseline changes
+    pub fn text(frame: &Frame) -> String {
+        let mut out = String::new();
+        let mut baseline = None;
+        walk(frame, &mut |ts, item| {
+            let FrameItem::Text(text) = item else {
+                return;
+            };
+
+            let y = Point::zero().transform(ts).y;
+            if baseline.is_some_and(|baseline| baseline != y) {
+                out.push('\n');
+            }
+            baseline = Some(y);
+            out.push_str(&text.text);
+        });
+
+        out
+    }
+
+    /// The areas of the occurrences of `query` in the text runs of a frame
+    ///
+    /// A run is text in one font and style on one line, so matches across runs aren't found.
+    pub fn find_text(frame: &Frame, query: &str, case_sensitive: bool) -> Vec<Rect> {
+        let same =
+            |a: char, b: char| a == b || (!case_sensitive && a.to_lowercase().eq(b.to_lowercase()));
+
+        let mut found = Vec::new();
+        walk(frame, &mut |ts, item| {
+            let FrameItem::Text(text) = item else {
+                return;
+            };
+
+            for (start, _) in text.text.char_indices() {
+                let mut rest = text.text[start..].char_indices();
+                let mut len = 0;
+                let matched = query.chars().all(|q| {
+                    rest.next().is_some_and(|(i, c)| {
+                        len = i + c.len_utf8();
+                        same(c, q)
+                    })
+                });
+                if !matched || query.is_empty() {
+                    continue;
+                }
+
+                let range = start..start + len;
+                let mut x = Abs::zero();
+                let mut span: Option<(Abs, Abs)> = None;
+                for glyph in &text.glyphs {
+                    let advance = glyph.x_advance.at(text.size);
+                    let glyph_range = glyph.range();
+                    if glyph_range.start < range.end && range.start < glyph_range.end {
+                        let (min, _) = span.unwrap_or((x, x));
+                        span = Some((min, x + advance));
+                    }
+                    x += advance;
+                }
+
+                if let Some((min, max)) = span {
+                    let metrics = text.font.metrics();
+                    found.push(Rect::transformed(
+                        Point::new(min, -metrics.ascender.at(text.size)),
+                        Point::new(max, -metrics.descender.at(text.size)),
+                        ts,
+                    ));
+                }
+            }
+        });
+
+        found
+    }
+
+    /// A glyph placed on a frame, see `glyphs`
+    pub struct PlacedGlyph {
+        /// Where the glyph starts on the baseline, after its offset
+        pub origin: Point,
+        pub advance: Abs,
+        pub ascent: Abs,
+        pub descent: Abs,
+        pub size: Abs,
+        /// The text the glyph was shaped from, shared by the glyphs of a ligature or cluster
+        pub cluster: String,
+    }
+
+    /// The glyphs in the text runs of a frame, in the order typst lays them out
+    pub fn glyphs(frame: &Frame) -> Vec<PlacedGlyph> {
+        let mut glyphs = Vec::new();
+        walk(frame, &mut |ts, item| {
+            let FrameItem::Text(text) = item else {
+                return;
+            };
+
+            let metrics = text.font.metrics();
+            let mut x = Abs::zero();
+            for glyph in &text.glyphs {
+                let offset = glyph.x_offset.at(text.size);
+                let advance = glyph.x_advance.at(text.size);
+                glyphs.push(PlacedGlyph {
+                    origin: Point::with_x(x + offset).transform(ts),
+                    advance,
+                    ascent: metrics.ascender.at(text.size),
+                    descent: -metrics.descender.at(text.size),
+                    size: text.size,
+                    cluster: text.text.get(glyph.range()).unwrap_or_default().to_string(),
+                });
+                x += advance;
+            }
+        });
+
+        glyphs
+    }
+
+    /// Where the first element with the given label starts, e.g. the baseline for inline elements
+    pub fn element_position(frame: &Frame, label: Label) -> Option<Point> {
+        let mut position = None;
+        walk(frame, &mut |ts, item| {
+            if let FrameItem::Tag(Tag::Start(content, ..)) = item
+                && position.is_none()
+                && content.label() == Some(label)
+            {
+                position = Some(Point::zero().transform(ts));
+            }
+        });
+
+        position
+    }
+
+    /// A frame showing only the given area of another one
+    pub fn crop(frame: &Frame, rect: Rect) -> Frame {
+        let mut cropped = Frame::hard(rect.size());
+        cropped.push_frame(-rect.min, frame.clone());
+
+        cropped
+    }
+
+    /// Visits every item of a frame and its nested groups,
+    /// passing the transform from the page origin to the item
+    pub fn walk(frame: &Frame, f: &mut impl FnMut(Transform, &FrameItem)) {
+        walk_with(frame, Transform::identity(), f);
+    }
+
+    /// The number of items in a frame and its groups
+    pub fn item_count(frame: &Frame) -> usize {
+        let mut count = 0;
+        walk(frame, &mut |_, _| count += 1);
+
+        count
+    }
+
+    /// Visits every fill and stroke paint of a frame, including the contents of tilings
+    pub fn paints(frame: &Frame, f: &mut impl FnMut(&Paint)) {
+        walk(frame, &mut |_, item| {
+            let (fill, stroke) = match item {
+                FrameItem::Text(text) => (Some(&text.fill), text.stroke.as_ref()),
+                FrameItem::Shape(shape, _) => (shape.fill.as_ref(), shape.stroke.as_ref()),
+                _ => return,
+            };
+
+            for paint in fill.into_iter().chain(stroke.map(|stroke| &stroke.paint)) {
+                f(paint);
+                if let Paint::Tiling(tiling) = paint {
+                    paints(tiling.frame(), f);
+                }
+            }
+        });
+    }
+
+    fn walk_with(frame: &Frame, ts: Transform, f: &mut impl FnMut(Transform, &FrameItem)) {
+        for (pos, item) in frame.items() {
+            let ts = ts.pre_concat(Transform::translate(pos.x, pos.y));
+            f(ts, item);
+
+            if let FrameItem::Group(group) = item {
+                walk_with(&group.frame, ts.pre_concat(group.transform), f);
+            }
+        }
+    }
+}
+
+mod diag {
+    use std::{fmt::Write, ops::Range};
+
+    use serde_json::{Value, json};
+    use typst::{
+        World,
+        diag::{Severity, SourceDiagnostic},
+        syntax::{Source, Span},
+    };
+
+    use crate::fs::FS;
+
+    /// Where a span points to in its source file
+    pub struct Location {
+        /// As returned by `FS::path`
+        pub path: String,
+        pub source: Source,
+        pub range: Range<usize>,
+        /// Zero-based line of the span start
+        pub line: usize,
+        /// Zero-based column of the span start, in characters
+        pub column: usize,
+    }
+
+    impl Location {
+        /// The byte range of the whole line the span starts on
+        pub fn line_range(&self) -> Range<usize> {
+            let text = self.source.text();
+            let start = text[..self.range.start].rfind('\n').map_or(0, |i| i + 1);
+            let end = text[self.range.start..]
+                .find('\n')
+                .map_or(text.len(), |i| self.range.start + i);
+
+            start..end
+        }
+    }
+
+    pub fn locate(world: &dyn World, files: &FS, span: Span) -> Option<Location> {
+        let source = world.source(span.id()?).ok()?;
+        locate_in(source, files, span)
+    }
+
+    /// Like `locate`, for a span in a source that the world doesn't have to load
+    pub fn locate_in(source: Source, files: &FS, span: Span) -> Option<Location> {
+        let id = span.id()?;
+        let range = source.range(span)?;
+
+        let before = &source.text()[..range.start];
+        let line = before.matches('\n').count();
+        let column = before[before.rfind('\n').map_or(0, |i| i + 1)..]
+            .chars()
+            .count();
+
+        Some(Location {
+            path: files.path(id),
+            source,
+            range,
+            line,
+            column,
+        })
+    }
+
+    #[derive(Clone, Copy)]
+    pub enum Format {
+        Legacy,
+        Json,
+    }
+
+    pub fn legacy(diag: &SourceDiagnostic) -> String {
+        format!(
+            "SPAN: {:?} ||| MSG: {} ||| HINT: {}",
+            diag.span,
+            diag.message.clone(),
+            diag.hints
+                .iter()
+                .map(|spanned| spanned.v.clone())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+
+    pub fn json(world: &dyn World, files: &FS, diag: &SourceDiagnostic) -> Value {
+        let loc = locate(world, files, diag.span);
+
+        json!({
+            "severity": severity(diag),
+            "message": diag.message.as_str(),
+            "hints": diag.hints.iter().map(|hint| hint.v.as_str()).collect::<Vec<_>>(),
+            "file": loc.as_ref().map(|loc| &loc.path),
+            "line": loc.as_ref().map(|loc| loc.line),
+            "column": loc.as_ref().map(|loc| loc.column),
+            "start": loc.as_ref().map(|loc| loc.range.start),
+            "end": loc.as_ref().map(|loc| loc.range.end),
+            "snippet": loc.as_ref().map(|loc| &loc.source.text()[loc.range.clone()]),
+        })
+    }
+
+    pub fn severity(diag: &SourceDiagnostic) -> &'static str {
+        match diag.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    /// Formats a diagnostic like the typst CLI does,
+    /// with the offending line and a caret underline
+    pub fn pretty(world: &dyn World, files: &FS, diag: &SourceDiagnostic) -> String {
+        let mut out = format!("{}: {}\n", severity(diag), diag.message);
+
+        if let Some(loc) = locate(world, files, diag.span) {
+            let lines = loc.line_range();
+            let text = &loc.source.text()[lines.clone()];
+            let width = loc.source.text()[loc.range.start..loc.range.end.min(lines.end)]
+                .chars()
+                .count()
+                .max(1);
+
+            let number = (loc.line + 1).to_string();
+            let pad = " ".repeat(number.len());
+
+            let _ = writeln!(out, "{pad}--> {}:{}:{}", loc.path, number, loc.column + 1);
+            let _ = writeln!(out, "{pad} |");
+            let _ = writeln!(out, "{number} | {text}");
+            let _ = writeln!(
+                out,
+                "{pad} | {}{}",
+                " ".repeat(loc.column),
+                "^".repeat(width)
+            );
+        }
+
+        for hint in &diag.hints {
+            let _ = writeln!(out, "  = hint: {}", hint.v);
+        }
+
+        out
+    }
+}
+
+/// Returns the version of typst this build links against, e.g. `0.14.2`
+#[wasm_bindgen]
+pub fn typst_version() -> String {
+    PackageVersion::compiler().to_string()
+}
+
+mod imports {
+    use std::{
+        collections::HashSet,
+        ops::Range,
+        path::{Component, Path, PathBuf},
+    };
+
+    use typst::syntax::{
+        LinkedNode, Span, SyntaxKind, SyntaxNode,
+        ast::{self, AstNode},
+    };
+
+    /// The paths of the files a source imports or includes, as written.
+    /// Packages and paths computed at runtime are skipped.
+    pub fn targets(text: &str) -> Vec<String> {
+        directives(&typst::syntax::parse(text))
+            .into_iter()
+            .map(|(_, target)| target)
+            .filter(|target| !target.starts_with('@'))
+            .collect()
+    }
+
+    /// The paths and packages a syntax tree imports or includes, as written, with the span
+    /// of each path. Paths computed at runtime are skipped.
+    pub fn directives(node: &SyntaxNode) -> Vec<(Span, String)> {
+        let mut targets = Vec::new();
+        collect(node, &mut targets);
+
+        targets
+    }
+
+    fn collect(node: &SyntaxNode, targets: &mut Vec<(Span, String)>) {
+        let source = if let Some(import) = node.cast::<ast::ModuleImport>() {
+            Some(import.source())
+        } else {
+            node.cast::<ast::ModuleInclude>()
+                .map(|include| include.source())
+        };
+
+        if let Some(ast::Expr::Str(path)) = source {
+            targets.push((path.span(), path.get().to_string()));
+        }
+
+        for child in node.children() {
+            collect(child, targets);
+        }
+    }
+
+    /// The text of a file with its local `#include`s and `#import "…": …`s replaced by the
+    /// files themselves, recursively
+    ///
+    /// `read` returns the text of a file by path. Imports of a file that was already
+    /// inlined in the same or an enclosing scope are dropped. Imports of packages or whole
+    /// modules (`#import "a.typ"` or `as`), imports with renamed items (`x as y`), imports
+    /// and includes in code and ones that would be cyclic or can't be read stay as they are.
+    pub fn flatten(
+        path: &Path,
+        read: &dyn Fn(&Path) -> Option<String>,
+        root: &Path,
+        stack: &mut Vec<PathBuf>,
+        imported: &mut HashSet<PathBuf>,
+    ) -> Option<String> {
+        let text = read(path)?;
+        let root_node = typst::syntax::parse(&text);
+
+        let mut directives = Vec::new();
+        find_directives(LinkedNode::new(&root_node), &mut directives);
+
+        stack.push(path.to_path_buf());
+        // The blocks entered so far, with the imports from before each of them
+        let mut scopes: Vec<(Range<usize>, HashSet<PathBuf>)> = Vec::new();
+        let mut out = String::with_capacity(text.len());
+        let mut last = 0;
+        for directive in directives {
+            let Directive {
+                range,
+                target,
+                include,
+                blocks,
+            } = directive;
+
+            // Imports inlined in a block that was left don't count anymore
+            while scopes
+                .last()
+                .is_some_and(|(block, _)| !blocks.contains(block))
+            {
+                if let Some((_, before)) = scopes.pop() {
+                    *imported = before;
+                }
+            }
+            for block in &blocks[scopes.len()..] {
+                scopes.push((block.clone(), imported.clone()));
+            }
+
+            let next = resolve(path, &target, root);
+            if stack.contains(&next) {
+                continue;
+            }
+
+            let replacement = if include {
+                // The include's definitions stay inside its block, so imports it inlines
+                // don't count for the enclosing file
+                let mut scoped = imported.clone();
+                flatten(&next, read, root, stack, &mut scoped).map(|text| format!("#[\n{text}\n]"))
+            } else if imported.contains(&next) {
+                Some(String::new())
+            } else {
+                imported.insert(next.clone());
+                flatten(&next, read, root, stack, imported)
+            };
+
+            if let Some(replacement) = replacement {
+                out.push_str(&text[last..range.start]);
+                out.push_str(&replacement);
+                last = range.end;
+            }
+        }
+        out.push_str(&text[last..]);
+        stack.pop();
+        if let Some((_, before)) = scopes.into_iter().next() {
+            *imported = before;
+        }
+
+        Some(out)
+    }
+
+    /// An `#include` or `#import "…": …` that `flatten` can inline
+    struct Directive {
+        /// Including `#` and `;`
+        range: Range<usize>,
+        target: String,
+        include: bool,
+        /// The content and code blocks it's in, outermost first
+        blocks: Vec<Range<usize>>,
+    }
+
+    /// The `#include`s and `#import "…": …`s of local files directly in markup,
+    /// without renamed items
+    fn find_directives(node: LinkedNode, out: &mut Vec<Directive>) {
+        let source = if let Some(import) = node.cast::<ast::ModuleImport>() {
+            let renames = match import.imports() {
+                Some(ast::Imports::Items(items)) => items
+                    .iter()
+                    .any(|item| matches!(item, ast::ImportItem::Renamed(_))),
+                Some(ast::Imports::Wildcard) => false,
+                None => true,
+            };
+            (!renames && import.new_name().is_none()).then(|| (import.source(), false))
+        } else {
+            node.cast::<ast::ModuleInclude>()
+                .map(|include| (include.source(), true))
+        };
+
+        let in_markup = node.parent_kind() == Some(SyntaxKind::Markup)
+            && node.prev_sibling_kind() == Some(SyntaxKind::Hash);
+        if let Some((ast::Expr::Str(path), include)) = source
+            && in_markup
+            && !path.get().starts_with('@')
+        {
+            let start = node
+                .prev_sibling()
+                .map_or(node.offset(), |hash| hash.offset());
+            let end = match node.next_sibling() {
+                Some(next) if next.kind() == SyntaxKind::Semicolon => next.range().end,
+                _ => node.range().end,
+            };
+            let mut blocks = Vec::new();
+            let mut parent = node.parent();
+            while let Some(ancestor) = parent {
+                if matches!(
+                    ancestor.kind(),
+                    SyntaxKind::ContentBlock | SyntaxKind::CodeBlock
+                ) {
+                    blocks.push(ancestor.range());
+                }
+                parent = ancestor.parent();
+            }
+            blocks.reverse();
+
+            out.push(Directive {
+                range: start..end,
+                target: path.get().to_string(),
+                include,
+                blocks,
+            });
+            return;
+        }
+
+        for child in node.children() {
+            find_directives(child, out);
+        }
+    }
+
+    /// Resolves an import target the way typst does: absolute paths against the root,
+    /// relative ones against the importing file
+    pub fn resolve(from: &Path, target: &str, root: &Path) -> PathBuf {
+        let joined = match target.strip_prefix('/') {
+            Some(absolute) => root.join(absolute),
+            None => from.parent().unwrap_or(Path::new("")).join(target),
+        };
+
+        let mut path = PathBuf::new();
+        for component in joined.components() {
+            match component {
+                Component::ParentDir => {
+                    path.pop();
+                }
+                Component::Normal(part) => path.push(part),
+                _ => {}
+            }
+        }
+
+        path
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::{
+            collections::{HashMap, HashSet},
+            path::{Path, PathBuf},
+        };
+
+        use super::{flatten, resolve};
+
+        #[test]
+        fn absolute_imports_resolve_against_the_root() {
+            let from = Path::new("src/chapters/intro.typ");
+
+            assert_eq!(
+                resolve(from, "/lib/util.typ", Path::new("")),
+                PathBuf::from("lib/util.typ")
+            );
+            assert_eq!(
+                resolve(from, "/lib/util.typ", Path::new("proj")),
+                PathBuf::from("proj/lib/util.typ")
+            );
+        }
+
+        #[test]
+        fn relative_imports_resolve_against_the_importing_file() {
+            let from = Path::new("src/main.typ");
+
+            assert_eq!(
+                resolve(from, "foo.typ", Path::new("")),
+                PathBuf::from("src/foo.typ")
+            );
+            assert_eq!(
+                resolve(from, "../lib/util.typ", Path::new("")),
+                PathBuf::from("lib/util.typ")
+            );
+        }
+
+        #[test]
+        fn imports_in_blocks_dont_hide_later_imports() {
+            let files = HashMap::from([
+                (
+                    "main.typ",
+                    "#[ #import \"a.typ\": x ]\n#import \"a.typ\": x\n#x",
+                ),
+                ("a.typ", "#let x = 1"),
+            ]);
+            let read = |path: &Path| files.get(path.to_str()?).map(|text| text.to_string());
+
+            let flat = flatten(
+                Path::new("main.typ"),
+                &read,
+                Path::new(""),
+                &mut Vec::new(),
+                &mut HashSet::new(),
+            );
+
+            assert_eq!(flat.as_deref(), Some("#[ #let x = 1 ]\n#let x = 1\n#x"));
+        }
+    }
+}
+
+mod lint {
+    use typst::syntax::{
+        LinkedNode, Source, Span, SyntaxKind,
+        ast::{self, AstNode},
+    };
+
+    pub struct Finding {
+        pub rule: &'static str,
+        pub span: Span,
+        pub message: String,
+    }
+
+    /// A check over all sources of the project
+    pub type Rule = fn(&[Source]) -> Vec<Finding>;
+
+    /// The built-in rules, new ones only need to be added here
+    pub const RULES: &[Rule] = &[unused_imports, unreferenced_labels, undefined_references];
+
+    fn descendants(source: &Source) -> Vec<LinkedNode<'_>> {
+        fn collect<'a>(node: LinkedNode<'a>, out: &mut Vec<LinkedNode<'a>>) {
+            for child in node.children() {
+                collect(child, out);
+            }
+            out.push(node);
+        }
+
+        let mut nodes = Vec::new();
+        collect(LinkedNode::new(source.root()), &mut nodes);
+        nodes
+    }
+
+    /// `unused-import`: names imported with `#import "…": name` that the file never uses
+    fn unused_imports(sources: &[Source]) -> Vec<Finding> {
+        let mut findings = Vec::new();
+
+        for source in sources {
+            let nodes = descendants(source);
+            for node in &nodes {
+                let Some(ast::Imports::Items(items)) = node
+                    .cast::<ast::ModuleImport>()
+                    .and_then(|import| import.imports())
+                else {
+                    continue;
+                };
+
+                for item in items.iter() {
+                    let name = item.bound_name();
+                    let span = name.to_untyped().span();
+                    let used = nodes.iter().any(|node| {
+                        matches!(node.kind(), SyntaxKind::Ident | SyntaxKind::MathIdent)
+                            && node.text() == name.get()
+                            && node.span() != span
+                    });
+
+                    if !used {
+                        findings.push(Finding {
+                            rule: "unused-import",
+                            span,
+                            message: format!("`{}` is imported but never used", name.get()),
+                        });
+                    }
+                }
+            }
+        }
+
+        findings
+    }
+
+    /// Labels attached in markup and the label names referenced anywhere,
+    /// by `@name` or a `<name>` value in code
+    fn labels(sources: &[Source]) -> (Vec<(String, Span)>, Vec<(String, Span)>) {
+        let mut defined = Vec::new();
+        let mut referenced = Vec::new();
+
+        for source in sources {
+            for node in descendants(source) {
+                if let Some(label) = node.cast::<ast::Label>() {
+                    let entry = (label.get().to_string(), node.span());
+                    match node.parent_kind() {
+                        Some(SyntaxKind::Markup) => defined.push(entry),
+                        _ => referenced.push(entry),
+                    }
+                } else if let Some(reference) = node.cast::<ast::Ref>() {
+                    referenced.push((reference.target().to_string(), node.span()));
+                }
+            }
+        }
+
+        (defined, referenced)
+    }
+
+    /// `unreferenced-label`: labels that nothing refers to
+    fn unreferenced_labels(sources: &[Source]) -> Vec<Finding> {
+        let (defined, referenced) = labels(sources);
+
+        defined
+            .into_iter()
+            .filter(|(name, _)| !referenced.iter().any(|(other, _)| other == name))
+            .map(|(name, span)| Finding {
+                rule: "unreferenced-label",
+                span,
+                message: format!("label `<{name}>` is never referenced"),
+            })
+            .collect()
+    }
+
+    /// `undefined-reference`: `@name` without a `<name>` label.
+    /// Skipped if the project has a bibliography, since `@key` may then be a citation.
+    fn undefined_references(sources: &[Source]) -> Vec<Finding> {
+        let has_bibliography = sources.iter().any(|source| {
+            descendants(source)
+                .iter()
+                .any(|node| node.kind() == SyntaxKind::Ident && node.text() == "bibliography")
+        });
+        if has_bibliography {
+            return Vec::new();
+        }
+
+        let (defined, referenced) = labels(sources);
+        referenced
+            .into_iter()
+            .filter(|(name, _)| !defined.iter().any(|(other, _)| other == name))
+            .map(|(name, span)| Finding {
+                rule: "undefined-reference",
+                span,
+                message: format!("label `<{name}>` does not exist"),
+            })
+            .collect()
+    }
+}
+
+mod prelude {
+    use std::{collections::BTreeMap, fmt::Write};
+
+    /// The element kinds `set_numbering` supports, with the rule applying a numbering to them
+    pub const NUMBERING_RULES: &[(&str, &str)] = &[
+        ("heading", "#set heading(numbering: {})"),
+        ("figure", "#set figure(numbering: {})"),
+        (
+            "table",
+            "#show figure.where(kind: table): set figure(numbering: {})",
+        ),
+        (
+            "image",
+            "#show figure.where(kind: image): set figure(numbering: {})",
+        ),
+        ("equation", "#set math.equation(numbering: {})"),
+        ("footnote", "#set footnote(numbering: {})"),
+        ("page", "#set page(numbering: {})"),
+    ];
+
+    /// Defaults applied with set rules before the main file
+    #[derive(Clone, Default)]
+    pub struct Prelude {
+        pub lang: Option<(String, Option<String>)>,
+        pub text: TextDefaults,
+        pub hyphenate: Option<bool>,
+        /// `ltr` or `rtl`
+        pub dir: Option<&'static str>,
+        /// Paragraph leading in em
+        pub leading: Option<f64>,
+        /// Top, right, bottom and left page margins in points
+        pub margins: Option<[f64; 4]>,
+        /// Text drawn behind every page and its opacity from 0 to 1
+        pub watermark: Option<(String, f64)>,
+        /// The number of the first page
+        pub page_start: Option<usize>,
+        /// Numbering patterns by element kind, see `NUMBERING_RULES`
+        pub numbering: BTreeMap<String, String>,
+        /// Typst code from `set_prelude`, applied after the other defaults
+        pub code: String,
+    }
+
+    #[derive(Clone, Default)]
+    pub struct TextDefaults {
+        pub smartquote: Option<bool>,
+        /// In points
+        pub size: Option<f64>,
+        pub font: Option<Vec<String>>,
+    }
+
+    impl Prelude {
+        /// Only the defaults for text, without the ones that add content or change pages
+        pub fn text_only(&self) -> Self {
+            Self {
+                lang: self.lang.clone(),
+                text: self.text.clone(),
+                dir: self.dir,
+                leading: self.leading,
+                ..Self::default()
+            }
+        }
+
+        /// The typst code applying the defaults, empty if there are none
+        pub fn rules(&self) -> String {
+            let mut rules = String::new();
+
+            if let Some((lang, region)) = &self.lang {
+                let region = region
+                    .as_ref()
+                    .map(|region| format!(", region: {}", string(region)))
+                    .unwrap_or_default();
+                let _ = writeln!(rules, "#set text(lang: {}{region})", string(lang));
+            }
+
+            if let Some(on) = self.text.smartquote {
+                let _ = writeln!(rules, "#set smartquote(enabled: {on})");
+            }
+            if let Some(size) = self.text.size {
+                let _ = writeln!(rules, "#set text(size: {size}pt)");
+            }
+            if let Some(font) = &self.text.font {
+                let font: String = font.iter().map(|family| string(family) + ", ").collect();
+                let _ = writeln!(rules, "#set text(font: ({font}))");
+            }
+            if let Some(on) = self.hyphenate {
+                let _ = writeln!(rules, "#set text(hyphenate: {on})");
+            }
+            if let Some(dir) = self.dir {
+                let _ = writeln!(rules, "#set text(dir: {dir})");
+            }
+            if let Some(leading) = self.leading {
+                let _ = writeln!(rules, "#set par(leading: {leading}em)");
+            }
+
+            if let Some([top, right, bottom, left]) = self.margins {
+                let _ = writeln!(
+                    rules,
+                    "#set page(margin: (top: {top}pt, right: {right}pt, bottom: {bottom}pt, left: {left}pt))"
+                );
+            }
+
+            if let Some((text, opacity)) = &self.watermark {
+                let transparency = (1.0 - opacity) * 100.0;
+                let _ = writeln!(
+                    rules,
+                    "#set page(background: place(center + horizon, rotate(-45deg, \
+                     text(size: 80pt, fill: gray.transparentize({transparency}%), {}))))",
+                    string(text)
+                );
+            }
+
+            for (kind, rule) in NUMBERING_RULES {
+                if let Some(pattern) = self.numbering.get(*kind) {
+                    let _ = writeln!(rules, "{}", rule.replace("{}", &string(pattern)));
+                }
+            }
+
+            if let Some(start) = self.page_start {
+                let _ = writeln!(rules, "#counter(page).update({start})");
+            }
+
+            if !self.code.is_empty() {
+                rules.push_str(&self.code);
+                if !self.code.ends_with('\n') {
+                    rules.push('\n');
+                }
+            }
+
+            rules
+        }
+    }
+
+    /// A typst string literal with the given content
+    pub fn string(text: &str) -> String {
+        let mut literal = String::from('"');
+        for c in text.chars() {
+            match c {
+                '"' => literal.push_str("\\\""),
+                '\\' => literal.push_str("\\\\"),
+                '\n' => literal.push_str("\\n"),
+                '\r' => literal.push_str("\\r"),
+                '\t' => literal.push_str("\\t"),
+                c if c.is_control() => {
+                    let _ = write!(literal, "\\u{{{:x}}}", c as u32);
+                }
+                c => literal.push(c),
+            }
+        }
+        literal.push('"');
+
+        literal
+    }
+}
+
+mod pdf {
+    use std::{collections::HashMap, fmt::Write};
+
+    /// Sets the creator and producer in the document info and XMP metadata
+    ///
+    /// Offsets in the PDF must stay the same, so the original values are blanked in place
+    /// and the new ones are appended as an incremental update: a new info dictionary, a new
+    /// XMP stream if there's an uncompressed one, and a cross-reference section for them.
+    /// Returns `None` and leaves the PDF as is if it has no cross-reference table to extend.
+    pub fn set_producer(pdf: &mut Vec<u8>, producer: &str) -> Option<()> {
+        let startxref = rfind(pdf, b"startxref")?;
+        let trailer = std::str::from_utf8(&pdf[rfind(&pdf[..startxref], b"trailer")?..startxref])
+            .ok()?
+            .to_string();
+        let prev: usize = token_after(&pdf[startxref..], b"startxref")?.parse().ok()?;
+        let mut size: usize = token_after(trailer.as_bytes(), b"/Size")?.parse().ok()?;
+        let root = reference_after(&trailer, "/Root")?;
+        let id = trailer
+            .find("/ID")
+            .and_then(|start| Some(&trailer[start..start + trailer[start..].find(']')? + 1]));
+
+        // The entries of the info dictionary other than the creator and producer
+        let (info, entries) = match reference_after(&trailer, "/Info") {
+            Some(info) => {
+                let body = &pdf[object(pdf, info)?];
+                let start = find(body, b"<<")?;
+                let end = start + skip(body, start);
+                let entries = without_keys(&body[start + 2..end - 2], &[b"/Creator", b"/Producer"]);
+                (info, entries)
+            }
+            None => {
+                size += 1;
+                ((size - 1, 0), Vec::new())
+            }
+        };
+        let metadata = rfind(pdf, b"<x:xmpmeta").and_then(|start| metadata_object(pdf, start));
+
+        blank(pdf);
+
+        let mut objects = Vec::new();
+        pdf.push(b'\n');
+        let string = text_string(producer);
+        objects.push((info, pdf.len()));
+        let _ = write!(Buffer(pdf), "{} {} obj\n<<", info.0, info.1);
+        pdf.extend(entries);
+        let _ = write!(
+            Buffer(pdf),
+            " /Creator {string} /Producer {string}>>\nendobj\n"
+        );
+
+        if let Some((number, dict, packet)) = metadata {
+            let packet = with_xmp_producer(&packet, producer);
+            objects.push((number, pdf.len()));
+            let _ = write!(Buffer(pdf), "{} {} obj\n<<", number.0, number.1);
+            pdf.extend(without_keys(&dict, &[b"/Length"]));
+            let _ = write!(
+                Buffer(pdf),
+                " /Length {}>>\nstream\n{packet}\nendstream\nendobj\n",
+                packet.len()
+            );
+        }
+
+        let xref = pdf.len();
+        let _ = writeln!(Buffer(pdf), "xref");
+        objects.sort();
+        for ((number, generation), offset) in objects {
+            let _ = write!(
+                Buffer(pdf),
+                "{number} 1\n{offset:010} {generation:05} n\r\n"
+            );
+        }
+        let _ = write!(
+            Buffer(pdf),
+            "trailer\n<<\n  /Size {size}\n  /Root {} {} R\n  /Info {} {} R\n",
+            root.0,
+            root.1,
+            info.0,
+            info.1
+        );
+        if let Some(id) = id {
+            let _ = writeln!(Buffer(pdf), "  {id}");
+        }
+        let _ = write!(
+            Buffer(pdf),
+            "  /Prev {prev}\n>>\nstartxref\n{xref}\n%%EOF\n"
+        );
+
+        Some(())
+    }
+
+    /// Writes formatted text to a byte buffer
+    struct Buffer<'a>(&'a mut Vec<u8>);
+
+    impl Write for Buffer<'_> {
+        fn write_str(&mut self, s: &str) -> std::fmt::Result {
+            self.0.extend_from_slice(s.as_bytes());
+            Ok(())
+        }
+    }
+
+    /// Overwrites the literal creator and producer strings with spaces
+    fn blank(pdf: &mut [u8]) {
+        let fields: [(&[u8], &[u8]); 4] = [
+            (b"/Creator (", b")"),
+            (b"/Producer (", b")"),
+            (b"<xmp:CreatorTool>", b"<"),
+            (b"<pdf:Producer>", b"<"),
+        ];
+
+        for (start, end) in fields {
+            let mut from = 0;
+            while let Some(i) = find(&pdf[from..], start) {
+                let value = from + i + start.len();
+                let Some(len) = find(&pdf[value..], end) else {
+                    break;
+                };
+                pdf[value..value + len].fill(b' ');
+                from = value + len;
+            }
+        }
+    }
+
+    /// A PDF text string, literal if it's printable ASCII and UTF-16 otherwise
+    fn text_string(text: &str) -> String {
+        if text.bytes().all(|b| b.is_ascii_graphic() || b == b' ') {
+            let escaped: String = text
+                .chars()
+                .flat_map(|c| match c {
+                    '(' | ')' | '\\' => vec!['\\', c],
+                    _ => vec![c],
+                })
+                .collect();
+            return format!("({escaped})");
+        }
+
+        let mut hex = String::from("<FEFF");
+        for unit in text.encode_utf16() {
+            let _ = write!(hex, "{unit:04X}");
+        }
+        hex.push('>');
+        hex
+    }
+
+    /// The XMP packet with the creator tool and producer replaced
+    fn with_xmp_producer(packet: &str, producer: &str) -> String {
+        let escaped = producer
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+
+        let mut packet = packet.to_string();
+        for tag in ["xmp:CreatorTool", "pdf:Producer"] {
+            let open = format!("<{tag}>");
+            let close = format!("</{tag}>");
+            if let Some(start) = packet.find(&open)
+                && let Some(len) = packet[start..].find(&close)
+            {
+                packet.replace_range(start + open.len()..start + len, &escaped);
+            }
+        }
+
+        packet
+    }
+
+    /// The number, dictionary contents and packet of the uncompressed stream object
+    /// containing the XMP metadata starting at `start`
+    fn metadata_object(pdf: &[u8], start: usize) -> Option<((usize, usize), Vec<u8>, String)> {
+        let obj = rfind(&pdf[..start], b" obj")?;
+        let header = String::from_utf8_lossy(&pdf[obj.saturating_sub(24)..obj]);
+        let mut parts = header.split_whitespace().rev();
+        let generation = parts.next()?.parse().ok()?;
+        let number = parts.next()?.parse().ok()?;
+
+        let dict_start = obj + find(&pdf[obj..start], b"<<")?;
+        let dict_end = dict_start + skip(pdf, dict_start);
+        let dict = &pdf[dict_start + 2..dict_end - 2];
+        if find(dict, b"/Filter").is_some() {
+            return None;
+        }
+
+        let stream = dict_end + find(&pdf[dict_end..start], b"stream")? + b"stream".len();
+        let body = &pdf[stream..];
+        let body = body.strip_prefix(b"\r\n").or(body.strip_prefix(b"\n"))?;
+        let packet = &body[..find(body, b"endstream")?];
+        let packet = std::str::from_utf8(packet).ok()?.trim_end();
+
+        Some(((number, generation), dict.to_vec(), packet.to_string()))
+    }
+
+    /// The range of the last `number generation obj … endobj` in the PDF
+    fn object(pdf: &[u8], (number, generation): (usize, usize)) -> Option<std::ops::Range<usize>> {
+        let header = format!("{number} {generation} obj");
+        let mut end = pdf.len();
+        let start = loop {
+            let start = rfind(&pdf[..end], header.as_bytes())?;
+            if start == 0 || pdf[start - 1].is_ascii_whitespace() {
+                break start;
+            }
+            end = start;
+        };
+        let len = find(&pdf[start..], b"endobj")?;
+
+        Some(start..start + len)
+    }
+
+    /// The dictionary entries of `dict` without the given keys, separated by spaces
+    fn without_keys(dict: &[u8], keys: &[&[u8]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut i = skip_whitespace(dict, 0);
+        while i < dict.len() {
+            let end = i + skip(dict, i);
+            if keys.contains(&&dict[i..end]) {
+                let value = skip_whitespace(dict, end);
+                i = skip_whitespace(dict, value + skip(dict, value));
+                continue;
+            }
+
+            out.push(b' ');
+            out.extend_from_slice(&dict[i..end]);
+            i = skip_whitespace(dict, end);
+        }
+
+        out
+    }
+
+    /// The length of the object starting at `start`: a string, dictionary, array or token
+    fn skip(bytes: &[u8], start: usize) -> usize {
+        let mut i = start;
+        match bytes.get(i) {
+            None => return 0,
+            Some(b'(') => {
+                let mut depth = 0;
+                while let Some(&b) = bytes.get(i) {
+                    i += 1;
+                    match b {
+                        b'\\' => i += 1,
+                        b'(' => depth += 1,
+                        b')' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Some(b'<') if bytes.get(i + 1) == Some(&b'<') => {
+                i = skip_whitespace(bytes, i + 2);
+                while i < bytes.len() && !bytes[i..].starts_with(b">>") {
+                    i = skip_whitespace(bytes, i + skip(bytes, i).max(1));
+                }
+                i += 2;
+            }
+            Some(b'<') => i += find(&bytes[i..], b">").map_or(bytes.len() - i, |end| end + 1),
+            Some(b'[') => {
+                i = skip_whitespace(bytes, i + 1);
+                while i < bytes.len() && bytes[i] != b']' {
+                    i = skip_whitespace(bytes, i + skip(bytes, i).max(1));
+                }
+                i += 1;
+            }
+            Some(_) => {
+                i += 1;
+                while i < bytes.len() && !is_delimiter(bytes[i]) {
+                    i += 1;
+                }
+            }
+        }
+
+        i.min(bytes.len()) - start
+    }
+
+    fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        i
+    }
+
+    fn is_delimiter(b: u8) -> bool {
+        b.is_ascii_whitespace() || b"()<>[]{}/%".contains(&b)
+    }
+
+    /// The token after `key`, e.g. the number after `/Size`
+    fn token_after<'a>(text: &'a [u8], key: &[u8]) -> Option<&'a str> {
+        let start = skip_whitespace(text, find(text, key)? + key.len());
+        let len = skip(text, start);
+
+        std::str::from_utf8(&text[start..start + len]).ok()
+    }
+
+    /// The object and generation number of the reference after `key`, e.g. `/Root 1 0 R`
+    fn reference_after(text: &str, key: &str) -> Option<(usize, usize)> {
+        let rest = &text[text.find(key)? + key.len()..];
+        let mut parts = rest.split_whitespace();
+        let number = parts.next()?.parse().ok()?;
+        let generation = parts.next()?.parse().ok()?;
+
+        (parts.next()? == "R").then_some((number, generation))
+    }
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+    }
+
+    fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack
+            .windows(needle.len())
+            .rposition(|window| window == needle)
+    }
+
+    /// The embedded font programs of a PDF with their compressed sizes, largest first.
+    ///
+    /// This only scans the uncompressed object structure, so fonts whose descriptors
+    /// are inside compressed object streams aren't found.
+    pub fn font_sizes(pdf: &[u8]) -> Vec<(String, usize)> {
+        let text = String::from_utf8_lossy(pdf);
+
+        let mut lengths = HashMap::new();
+        let mut descriptors = Vec::new();
+        for (number, object) in objects(&text) {
+            if let Some(name) = value_after(object, "/FontName /") {
+                let file = ["/FontFile ", "/FontFile2 ", "/FontFile3 "]
+                    .iter()
+                    .find_map(|key| value_after(object, key));
+                if let Some(file) = file.and_then(|file| file.parse::<usize>().ok()) {
+                    descriptors.push((name.to_string(), file));
+                }
+            }
+
+            // Only direct lengths, indirect ones would need another lookup
+            let dict = object.split("stream").next().unwrap_or(object);
+            if object.contains("stream")
+                && let Some(length) = value_after(dict, "/Length ")
+                && !dict.contains(&format!("/Length {length} 0 R"))
+                && let Ok(length) = length.parse::<usize>()
+            {
+                lengths.insert(number, length);
+            }
+        }
+
+        let mut sizes: Vec<_> = descriptors
+            .into_iter()
+            .filter_map(|(name, file)| Some((name, *lengths.get(&file)?)))
+            .collect();
+        sizes.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+
+        sizes
+    }
+
+    /// The `N 0 obj … endobj` objects of a PDF with their numbers
+    fn objects(text: &str) -> impl Iterator<Item = (usize, &str)> {
+        text.match_indices(" 0 obj").filter_map(|(start, marker)| {
+            let number = text[..start]
+                .rsplit(|c: char| !c.is_ascii_digit())
+                .next()?
+                .parse()
+                .ok()?;
+            let body = &text[start + marker.len()..];
+            let end = body.find("endobj").unwrap_or(body.len());
+
+            Some((number, &body[..end]))
+        })
+    }
+
+    /// The token following `key`, up to the next delimiter
+    fn value_after<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+        let rest = &text[text.find(key)? + key.len()..];
+        let end = rest
+            .find(|c: char| c.is_whitespace() || "/<>[]()".contains(c))
+            .unwrap_or(rest.len());
+
+        Some(&rest[..end]).filter(|value| !value.is_empty())
+    }
+}
+
+mod raster {
+    use tiny_skia::Pixmap;
+    use typst::layout::{Abs, Size};
+
+    /// The size in pixels typst-render gives a frame at `scale` pixels per point:
+    /// each side rounded to the nearest pixel and at least 1
+    pub fn size(size: Size, scale: f32) -> (f32, f32) {
+        let pixels = |pt: Abs| (scale * pt.to_f32()).round().max(1.0);
+
+        (pixels(size.x), pixels(size.y))
+    }
+
+    /// Whether tiny-skia can allocate a pixmap of this size: its length in bytes has to fit an `i32`
+    pub fn fits(width: f32, height: f32) -> bool {
+        f64::from(width) * f64::from(height) * 4.0 <= f64::from(i32::MAX)
+    }
+
+    /// The pixels of a pixmap as RGB, composited onto white
+    pub fn rgb_on_white(pixmap: &Pixmap) -> Vec<u8> {
+        pixmap
+            .pixels()
+            .iter()
+            .flat_map(|pixel| {
+                // Premultiplied, so compositing is adding the white that shows through
+                let white = 255 - pixel.alpha();
+                [
+                    pixel.red() + white,
+                    pixel.green() + white,
+                    pixel.blue() + white,
+                ]
+            })
+            .collect()
+    }
+
+    /// 64-bit FNV-1a over the width and height (little-endian u32) and the RGBA pixels
+    pub fn fnv1a(pixmap: &Pixmap) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let size = [pixmap.width().to_le_bytes(), pixmap.height().to_le_bytes()];
+        for byte in size.iter().flatten().chain(&rgba(pixmap)) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+
+        hash
+    }
+
+    /// The pixels of a pixmap as non-premultiplied RGBA
+    pub fn rgba(pixmap: &Pixmap) -> Vec<u8> {
+        pixmap
+            .pixels()
+            .iter()
+            .flat_map(|pixel| {
+                let color = pixel.demultiply();
+                [color.red(), color.green(), color.blue(), color.alpha()]
+            })
+            .collect()
+    }
+}
+
+mod svg {
+    use std::{collections::HashMap, fmt::Write};
+
+    use typst::{
+        layout::{Frame, FrameItem, Transform},
+        text::FontStyle,
+        visualize::Paint,
+    };
+    use typst_layout::Page;
+
+    use crate::frames;
+
+    /// Renders a page with its text as `<text>` elements referencing fonts by family,
+    /// instead of as glyph outlines
+    ///
+    /// The text is appended on top of the page, outside of any group or clip path.
+    pub fn with_text_elements(page: &Page) -> String {
+        let mut page = page.clone();
+        let text = text_elements(&page.frame);
+        page.frame = without_text(&page.frame);
+
+        let mut svg = typst_svg::svg(&page);
+        let end = svg.rfind("</svg>").unwrap_or(svg.len());
+        svg.insert_str(end, &text);
+
+        svg
+    }
+
+    fn without_text(frame: &Frame) -> Frame {
+        let mut out = Frame::hard(frame.size());
+        if frame.has_baseline() {
+            out.set_baseline(frame.baseline());
+        }
+
+        for (pos, item) in frame.items() {
+            match item {
+                FrameItem::Text(_) => {}
+                FrameItem::Group(group) => {
+                    let mut group = group.clone();
+                    group.frame = without_text(&group.frame);
+                    out.push(*pos, FrameItem::Group(group));
+                }
+                _ => out.push(*pos, item.clone()),
+            }
+        }
+
+        out
+    }
+
+    fn text_elements(frame: &Frame) -> String {
+        let mut out = String::new();
+        frames::walk(frame, &mut |ts, item| {
+            let FrameItem::Text(text) = item else {
+                return;
+            };
+
+            let info = text.font.info();
+            let fill = match &text.fill {
+                Paint::Solid(color) => color.to_hex().to_string(),
+                _ => "#000000".into(),
+            };
+            let style = match info.variant.style {
+                FontStyle::Normal => "normal",
+                _ => "italic",
+            };
+
+            // One x position per character, splitting ligatures evenly
+            let mut xs = Vec::new();
+            let mut x = 0.0;
+            for glyph in &text.glyphs {
+                let advance = glyph.x_advance.at(text.size).to_pt();
+                let offset = glyph.x_offset.at(text.size).to_pt();
+                let chars = text.text[glyph.range()].chars().count().max(1);
+                for i in 0..chars {
+                    xs.push(format!(
+                        "{:.2}",
+                        x + offset + advance * i as f64 / chars as f64
+                    ));
+                }
+                x += advance;
+            }
+
+            let _ = write!(
+                out,
+                r#"<text transform="{}" x="{}" font-family="{}" font-size="{:.2}" font-weight="{}" font-style="{style}" fill="{fill}" xml:space="preserve">{}</text>"#,
+                matrix(ts),
+                xs.join(" "),
+                escape(&info.family),
+                text.size.to_pt(),
+                info.variant.weight.to_number(),
+                escape(&text.text),
+            );
+        });
+
+        out
+    }
+
+    fn matrix(ts: Transform) -> String {
+        format!(
+            "matrix({} {} {} {} {} {})",
+            ts.sx.get(),
+            ts.ky.get(),
+            ts.kx.get(),
+            ts.sy.get(),
+            ts.tx.to_pt(),
+            ts.ty.to_pt()
+        )
+    }
+
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+
+    /// Changes the `width` and `height` of the page `<svg>` elements from points to
+    /// pixels at `ppi` pixels per inch, keeping the viewBox and coordinates in points
+    pub fn pixel_size(svg: &str, ppi: f64) -> String {
+        let mut out = String::with_capacity(svg.len());
+        let mut rest = svg;
+        while let Some(start) = rest.find("<svg class=\"typst-doc\"") {
+            let end = rest[start..]
+                .find('>')
+                .map_or(rest.len(), |end| start + end);
+            out.push_str(&rest[..start]);
+
+            let mut tag = rest[start..end].to_string();
+            for attr in ["width=\"", "height=\""] {
+                let Some(value) = tag.find(&format!(" {attr}")).map(|i| i + 1 + attr.len()) else {
+                    continue;
+                };
+                let len = tag[value..].find('"').unwrap_or(0);
+                let pt = tag[value..value + len]
+                    .strip_suffix("pt")
+                    .and_then(|pt| pt.parse().ok());
+                if let Some(px) = pt.map(|pt: f64| pt * ppi / 72.0) {
+                    // Rounded like typst's own numbers, to avoid float noise
+                    let px = (px * 1e4).round() / 1e4;
+                    tag.replace_range(value..value + len, &format!("{px}px"));
+                }
+            }
+
+            out.push_str(&tag);
+            rest = &rest[end..];
+        }
+        out.push_str(rest);
+
+        out
+    }
+
+    /// Renames all element ids to `id0`, `id1`, … in order of definition,
+    /// updating `#id` and `url(#id)` references to them
+    pub fn stable_ids(svg: &str) -> String {
+        let mut ids = HashMap::new();
+        let mut rest = svg;
+        while let Some(start) = rest.find("id=\"") {
+            rest = &rest[start + 4..];
+            let Some(end) = rest.find('"') else {
+                break;
+            };
+            let next = ids.len();
+            ids.entry(&rest[..end]).or_insert(next);
+            rest = &rest[end..];
+        }
+
+        let mut out = String::with_capacity(svg.len());
+        let mut rest = svg;
+        loop {
+            let found = ["id=\"", "\"#", "(#"]
+                .iter()
+                .filter_map(|marker| rest.find(marker).map(|i| (i + marker.len(), *marker)))
+                .min();
+            let Some((start, marker)) = found else {
+                break;
+            };
+
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let terminator = if marker == "(#" { ')' } else { '"' };
+            let end = rest.find(terminator).unwrap_or(rest.len());
+            match ids.get(&rest[..end]) {
+                Some(n) => out.push_str(&format!("id{n}")),
+                None => out.push_str(&rest[..end]),
+            }
+            rest = &rest[end..];
+        }
+        out.push_str(rest);
+
+        out
+    }
+}
+
+/// Compiles a single typst source to SVG without keeping any state
+///
+/// Uses a fresh compiler with the default fonts and no other files, so the source
+/// can't import files, use images or custom fonts. Throws an array of errors
+/// formatted like `errors()` if compilation fails.
+#[wasm_bindgen]
+pub fn compile_svg(source: &str) -> Result<String, JsValue> {
+    let mut typ = TypJs::new();
+    typ.write("main.typ", source.into());
+
+    let svg = typ.svg();
+    if typ.error_count() > 0 {
+        let errors: Array = typ.errors().into_iter().map(JsValue::from).collect();
+        return Err(errors.into());
+    }
+
+    Ok(svg)
+}
+
+/// Returns whether the data is a font typst can use, with at least one face
+///
+/// Parses the data without adding it anywhere, e.g. to check an upload
+/// before passing it to `attach_font`.
+#[wasm_bindgen]
+pub fn is_valid_font(data: &[u8]) -> bool {
+    Font::iter(Bytes::new(data.to_vec())).next().is_some()
+}
+
+#[wasm_bindgen]
+pub struct TypJs {
+    lib: LazyHash<Library>,
+    book: LazyHash<FontBook>,
+    fonts: Vec<Font>,
+    /// For each of `fonts`, its index in `bundled_fonts()` if it's built in
+    bundled: Vec<Option<usize>>,
+    files: FileStore<fs::FS>,
+    errors: EcoVec<SourceDiagnostic>,
+    now: Time,
+    deterministic: bool,
+    /// The last successfully compiled document and its warnings, until something changes
+    document: Option<(Arc<PagedDocument>, EcoVec<SourceDiagnostic>)>,
+    /// The PDF generated from `document`
+    pdf: Option<Vec<u8>>,
+    /// Hashes of the pages at the last `changed_pages` call
+    page_hashes: Vec<u128>,
+    /// Hashes of the pages at the last `svg_diff` call
+    svg_hashes: Vec<u128>,
+    /// Files read during the last compilation
+    accessed: Mutex<HashSet<FileId>>,
+    error_format: diag::Format,
+    prelude: prelude::Prelude,
+    /// The source of `fs::FS::prelude`, if there is a prelude
+    wrapper: Option<Source>,
+    last_output_size: usize,
+    /// The baseline of the last `render_math` formula, in points from its top
+    last_baseline: f64,
+    warnings_as_errors: bool,
+    /// The version of the shared package store the caches were built with
+    packages_version: usize,
+    max_pages: usize,
+    /// Estimated bytes a document may take, see `set_memory_limit`
+    memory_limit: usize,
+    /// `sys.inputs`, built into `lib`
+    inputs: BTreeMap<String, String>,
+    /// Replaces the creator and producer in PDFs
+    pdf_producer: Option<String>,
+    /// Supplies missing fonts, see `set_font_provider`
+    font_provider: Option<Function>,
+    /// Families already asked from `font_provider`
+    requested_fonts: HashSet<String>,
+}
+
+#[wasm_bindgen]
+impl TypJs {
+    pub fn new() -> Self {
+        let fonts: Vec<Font> = bundled_fonts().collect();
+
+        let mut files = FileStore::new(fs::FS::new());
+        files
+            .loader_mut()
+            .write(Path::new("/main.typ"), Bytes::new("Hello"));
+
+        Self {
+            lib: LazyHash::new(Library::default()), // stdlib
+            book: LazyHash::new(FontBook::from_fonts(&fonts)),
+            bundled: (0..fonts.len()).map(Some).collect(),
+            fonts,
+            files,
+            errors: EcoVec::new(),
+            now: Time::system(),
+            deterministic: false,
+            document: None,
+            pdf: None,
+            page_hashes: Vec::new(),
+            svg_hashes: Vec::new(),
+            accessed: Mutex::new(HashSet::new()),
+            error_format: diag::Format::Legacy,
+            prelude: prelude::Prelude::default(),
+            wrapper: None,
+            last_output_size: 0,
+            last_baseline: 0.0,
+            warnings_as_errors: false,
+            packages_version: fs::packages_version(),
+            max_pages: 0,
+            memory_limit: 0,
+            inputs: BTreeMap::new(),
+            pdf_producer: None,
+            font_provider: None,
+            requested_fonts: HashSet::new(),
+        }
+    }
+
+    /// Returns an independent copy with the same files, fonts and settings
+    ///
+    /// File contents and fonts are shared, not duplicated.
+    pub fn snapshot(&self) -> TypJs {
+        Self {
+            lib: self.lib.clone(),
+            book: self.book.clone(),
+            fonts: self.fonts.clone(),
+            bundled: self.bundled.clone(),
+            files: FileStore::new(self.files.loader().clone()),
+            errors: self.errors.clone(),
+            now: Time::system(),
+            deterministic: self.deterministic,
+            document: self.document.clone(),
+            pdf: None,
+            page_hashes: self.page_hashes.clone(),
+            svg_hashes: self.svg_hashes.clone(),
+            accessed: Mutex::new(self.accessed.lock().unwrap().clone()),
+            error_format: self.error_format,
+            prelude: self.prelude.clone(),
+            wrapper: self.wrapper.clone(),
+            last_output_size: 0,
+            last_baseline: self.last_baseline,
+            warnings_as_errors: self.warnings_as_errors,
+            packages_version: self.packages_version,
+            max_pages: self.max_pages,
+            memory_limit: self.memory_limit,
+            inputs: self.inputs.clone(),
+            pdf_producer: self.pdf_producer.clone(),
+            font_provider: self.font_provider.clone(),
+            requested_fonts: self.requested_fonts.clone(),
+        }
+    }
+
+    /// Deletes a given file
+    pub fn delete(&mut self, path: &str) {
+        self.files.loader_mut().delete(Path::new(path));
+
+        self.invalidate();
+    }
+
+    /// Returns the paths to all files available to the compiler,
+    /// including `main.typ`.
+    ///
+    /// Paths do *NOT* start with `/`.
+    pub fn list(&self) -> Vec<String> {
+        self.files.loader().list()
+    }
+
+    /// Returns whether the file exists and typst can read it as source code
+    ///
+    /// Typst reads any file as bytes, but only UTF-8 files as source,
+    /// e.g. for `#include` or `#import`. The extension doesn't matter.
+    pub fn is_source(&self, filename: &str) -> bool {
+        let files = self.files.loader();
+        let path = Path::new(filename);
+
+        files.is_valid(path)
+            && files
+                .read(path)
+                .is_some_and(|data| std::str::from_utf8(&data).is_ok())
+    }
+
+    /// Returns a list of errors if the last compilation failed or warnings if it finished successfully
+    ///
+    /// The format of each entry is set with `set_error_format`
+    pub fn errors(&self) -> Vec<String> {
+        self.errors
+            .iter()
+            .map(|err| match self.error_format {
+                diag::Format::Legacy => diag::legacy(err),
+                diag::Format::Json => diag::json(self, self.files.loader(), err).to_string(),
+            })
+            .collect()
+    }
+
+    /// Compiles the document and calls `on_diagnostic(entry)` for each error or warning
+    ///
+    /// Each `entry` is formatted like in `errors()`. Typst reports diagnostics
+    /// only once compilation is done, so the calls happen right after it,
+    /// not while it's running. Exceptions thrown by the callback are ignored.
+    pub fn compile_with_callback(&mut self, on_diagnostic: &Function) {
+        self.compile();
+
+        for entry in self.errors() {
+            let _ = on_diagnostic.call1(&JsValue::NULL, &entry.into());
+        }
+    }
+
+    /// Returns the size in bytes of the output of the last `svg`, `pdf`, … call
+    ///
+    /// For methods returning several outputs, it's their total size.
+    /// A failed call counts too and sets the size to 0.
+    pub fn last_output_size(&self) -> usize {
+        self.last_output_size
+    }
+
+    /// Makes documents with more than `limit` pages fail, 0 (the default) means unlimited
+    ///
+    /// A document over the limit is an error, it's not truncated: the output methods
+    /// return empty output and `errors()` reports the page count. The limit is checked
+    /// after layout, so it protects the exports and the memory they need, not the layout itself.
+    pub fn set_max_pages(&mut self, limit: usize) {
+        self.max_pages = limit;
+    }
+
+    /// Makes documents estimated to need more than `bytes` of memory fail, 0 (the default)
+    /// means unlimited
+    ///
+    /// The estimate is the size of the files, plus 256 bytes per frame item, plus 4 bytes
+    /// per pt² of the largest page. It's a soft guard: it's checked after layout, like
+    /// `set_max_pages`, and real use can be higher.
+    /// Over the limit, sets the `errors` field and the outputs are empty
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        self.memory_limit = bytes;
+    }
+
+    /// Makes compilations with warnings fail
+    ///
+    /// When on, the warnings are reported as errors in `errors()` and
+    /// the output methods return empty output. The compilation itself is unchanged.
+    pub fn set_warnings_as_errors(&mut self, on: bool) {
+        self.warnings_as_errors = on;
+    }
+
+    /// Returns the number of errors of the last compilation, without formatting them
+    pub fn error_count(&self) -> usize {
+        self.errors
+            .iter()
+            .filter(|err| err.severity == Severity::Error)
+            .count()
+    }
+
+    /// Returns the number of warnings of the last compilation, without formatting them
+    pub fn warning_count(&self) -> usize {
+        self.errors
+            .iter()
+            .filter(|err| err.severity == Severity::Warning)
+            .count()
+    }
+
+    /// Sets the default text language and region, e.g. `"de"` and `"AT"`
+    ///
+    /// A prelude default, see `set_prelude`. An empty `lang` removes it.
+    pub fn set_language(&mut self, lang: &str, region: Option<String>) {
+        self.prelude.lang = (!lang.is_empty()).then(|| (lang.into(), region));
+
+        self.invalidate_output();
+    }
+
+    /// Turns hyphenation on or off for all text
+    ///
+    /// A prelude default, see `set_prelude`. Without it, typst hyphenates justified text only.
+    /// Words are only hyphenated if typst has hyphenation patterns for the text language,
+    /// see `set_language`.
+    pub fn set_hyphenate(&mut self, on: bool) {
+        self.prelude.hyphenate = Some(on);
+
+        self.invalidate_output();
+    }
+
+    /// Sets the default page margins in points
+    ///
+    /// A prelude default, see `set_prelude`. Invalid values are reported in `errors`.
+    pub fn set_margins(&mut self, top: f64, right: f64, bottom: f64, left: f64) {
+        let margins = [top, right, bottom, left];
+        if !margins
+            .iter()
+            .all(|margin| margin.is_finite() && *margin >= 0.0)
+        {
+            self.report(format!("invalid margins: {top}, {right}, {bottom}, {left}"));
+            return;
+        }
+
+        self.prelude.margins = Some(margins);
+        self.invalidate_output();
+    }
+
+    /// Stamps `text`, e.g. `"DRAFT"`, diagonally across the background of every page
+    ///
+    /// `opacity` goes from 0 to 1. A prelude default, see `set_prelude`, so a page
+    /// background set by the document replaces it. An empty `text` removes it.
+    pub fn set_watermark(&mut self, text: &str, opacity: f64) {
+        let opacity = if opacity.is_nan() {
+            1.0
+        } else {
+            opacity.clamp(0.0, 1.0)
+        };
+        self.prelude.watermark = (!text.is_empty()).then(|| (text.into(), opacity));
+
+        self.invalidate_output();
+    }
+
+    /// Sets the default numbering pattern of an element kind, e.g. `"heading"` and `"1.a"`
+    ///
+    /// Supported kinds:
+    /// - `heading`, `equation`, `footnote` and `page`: `#set heading(numbering: …)` etc.
+    /// - `figure`: all figures
+    /// - `table` and `image`: figures of that kind, overriding `figure` for them
+    ///
+    /// A prelude default, see `set_prelude`. An empty `format` removes it,
+    /// unknown kinds are reported in `errors`.
+    pub fn set_numbering(&mut self, kind: &str, format: &str) {
+        if !prelude::NUMBERING_RULES
+            .iter()
+            .any(|(known, _)| *known == kind)
+        {
+            self.report(format!("unknown numbering kind `{kind}`"));
+            return;
+        }
+
+        if format.is_empty() {
+            self.prelude.numbering.remove(kind);
+        } else {
+            self.prelude.numbering.insert(kind.into(), format.into());
+        }
+
+        self.invalidate_output();
+    }
+
+    /// Numbers the pages starting at `n`, e.g. to continue the pagination of another document
+    ///
+    /// A prelude default, see `set_prelude`. `n = 1` is typst's default.
+    pub fn set_page_start(&mut self, n: usize) {
+        self.prelude.page_start = (n != 1).then_some(n);
+
+        self.invalidate_output();
+    }
+
+    /// Sets typst code that runs before the main file on every compile, e.g. shared `#set` rules
+    ///
+    /// The prelude and the defaults of `set_language`, `set_margins` etc. run before the
+    /// main file, so rules set by the document itself take precedence. Names from `#let`
+    /// and `#import` don't reach the main file, and errors in the prelude point to
+    /// `typ-js-prelude.typ`. An empty `text` removes the prelude.
+    pub fn set_prelude(&mut self, text: &str) {
+        self.prelude.code = text.into();
+
+        self.invalidate_output();
+    }
+
+    /// Sets the default space between the lines of a paragraph, in em
+    ///
+    /// Measured from the bottom of one line to the top of the next, not like CSS `line-height`.
+    /// A prelude default, see `set_prelude`. Invalid values are reported in `errors`.
+    pub fn set_leading(&mut self, leading_em: f64) {
+        if !(leading_em.is_finite() && leading_em >= 0.0) {
+            self.report(format!("invalid leading: {leading_em}em"));
+            return;
+        }
+
+        self.prelude.leading = Some(leading_em);
+        self.invalidate_output();
+    }
+
+    /// Sets the default font size in points, e.g. for a large-print variant
+    ///
+    /// Same as `size` in `set_text_defaults`, see `set_prelude`.
+    /// Invalid sizes are reported in `errors`.
+    pub fn set_font_size(&mut self, size_pt: f64) {
+        if !(size_pt.is_finite() && size_pt > 0.0) {
+            self.report(format!("invalid font size: {size_pt}pt"));
+            return;
+        }
+
+        self.prelude.text.size = Some(size_pt);
+        self.invalidate_output();
+    }
+
+    /// Sets the default text direction: `"ltr"`, `"rtl"` or `"auto"`
+    ///
+    /// A prelude default, see `set_prelude`. `"auto"` derives the direction from the
+    /// language, other values are reported in `errors`. The direction orders the lines
+    /// of paragraphs, runs in another script inside a paragraph still go their own way.
+    pub fn set_direction(&mut self, dir: &str) {
+        self.prelude.dir = match dir {
+            "ltr" => Some("ltr"),
+            "rtl" => Some("rtl"),
+            "auto" => None,
+            _ => {
+                self.report(format!("invalid text direction `{dir}`"));
+                return;
+            }
+        };
+
+        self.invalidate_output();
+    }
+
+    /// Sets text defaults from a JSON object, replacing the previous ones
+    ///
+    /// Supported keys:
+    /// - `smartquote`: `bool`, whether quotes are made typographic
+    /// - `size`: `number`, the font size in points
+    /// - `font`: `string | string[]`, the font family or fallback list
+    ///
+    /// Prelude defaults, see `set_prelude`. Invalid keys are reported in `errors`.
+    pub fn set_text_defaults(&mut self, json: &str) {
+        let Ok(Value::Object(map)) = serde_json::from_str::<Value>(json) else {
+            self.report("text defaults must be a JSON object".into());
+            return;
+        };
+
+        let mut defaults = prelude::TextDefaults::default();
+        for (key, value) in map {
+            match (key.as_str(), value) {
+                ("smartquote", Value::Bool(on)) => defaults.smartquote = Some(on),
+                ("size", Value::Number(size)) if size.as_f64().is_some_and(|size| size > 0.0) => {
+                    defaults.size = size.as_f64();
+                }
+                ("font", Value::String(family)) => defaults.font = Some(vec![family]),
+                ("font", Value::Array(families))
+                    if families.iter().all(|family| family.is_string()) =>
+                {
+                    defaults.font = families
+                        .iter()
+                        .map(|family| family.as_str().map(String::from))
+                        .collect();
+                }
+                (key, value) => self.report(format!("invalid text default `{key}`: {value}")),
+            }
+        }
+
+        self.prelude.text = defaults;
+        self.invalidate_output();
+    }
+
+    /// Sets `sys.inputs.<key>` to `value`, or removes it if `value` is omitted
+    ///
+    /// Like `typst compile --input key=value`, values are always strings.
+    pub fn set_input(&mut self, key: &str, value: Option<String>) {
+        match value {
+            Some(value) => self.inputs.insert(key.into(), value),
+            None => self.inputs.remove(key),
+        };
+
+        self.update_library();
+    }
+
+    /// Replaces all of `sys.inputs` with a JSON object of strings, e.g. `{ "draft": "true" }`
+    ///
+    /// If the JSON isn't an object of strings, reports it in `errors` and keeps the inputs.
+    pub fn set_inputs(&mut self, json: &str) {
+        let Ok(inputs) = serde_json::from_str::<BTreeMap<String, String>>(json) else {
+            self.report("inputs must be a JSON object of strings".into());
+            return;
+        };
+
+        self.inputs = inputs;
+        self.update_library();
+    }
+
+    /// Returns `sys.inputs` as a JSON object of strings, `{}` if none are set
+    pub fn inputs(&self) -> String {
+        serde_json::to_string(&self.inputs).unwrap_or_default()
+    }
+
+    /// Describes the active configuration, to explain why a document
+    /// behaves differently than with a plain `typst compile`
+    ///
+    /// Returns JSON `{ "library", "main", "root", "deterministic", "warnings_as_errors",
+    /// "max_pages", "memory_limit", "language", "prelude", "inputs" }`,
+    /// where `library` is `"default"` for the unmodified standard library or `"inputs"` if
+    /// `sys.inputs` is set, `language` is `{ lang, region }` or `null`, `prelude` is the
+    /// typst code applied before the main file and `inputs` is what `inputs()` returns.
+    /// Experimental features and the paper size aren't listed: typ-js enables no features,
+    /// and the paper is typst's default unless `prelude` or the document sets it.
+    pub fn library_info(&self) -> String {
+        let files = self.files.loader();
+        let language = self
+            .prelude
+            .lang
+            .as_ref()
+            .map(|(lang, region)| json!({ "lang": lang, "region": region }));
+
+        json!({
+            "library": if self.inputs.is_empty() { "default" } else { "inputs" },
+            "main": files.path(files.main),
+            "root": files.root().to_string_lossy(),
+            "deterministic": self.deterministic,
+            "warnings_as_errors": self.warnings_as_errors,
+            "max_pages": self.max_pages,
+            "memory_limit": self.memory_limit,
+            "language": language,
+            "prelude": self.prelude.rules(),
+            "inputs": self.inputs,
+        })
+        .to_string()
+    }
+
+    /// Sets the format of the entries returned by `errors()`
+    ///
+    /// - `"legacy"` (default): `SPAN: <span> ||| MSG: <message> ||| HINT: <hint>, <hint>`
+    /// - `"json"`: `{ "severity": "error" | "warning", "message": string, "hints": string[],
+    ///   "file": string | null, "line": number | null, "column": number | null,
+    ///   "start": number | null, "end": number | null, "snippet": string | null }`,
+    ///   with zero-based line and column, a byte range into the file
+    ///   and the source text the range covers
+    ///
+    /// Unknown formats are reported in `errors` and leave the format unchanged
+    pub fn set_error_format(&mut self, format: &str) {
+        match format {
+            "legacy" => self.error_format = diag::Format::Legacy,
+            "json" => self.error_format = diag::Format::Json,
+            _ => self.report(format!("unknown error format: {format}")),
+        }
+    }
+
+    /// Returns the errors or warnings of the last compilation formatted for humans,
+    /// with the offending source line and a caret underline
+    pub fn diagnostics_pretty(&self) -> String {
+        self.errors
+            .iter()
+            .map(|err| diag::pretty(self, self.files.loader(), err))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Sets the text content of a given `.typ` file.
+    ///
+    /// The root file is called `main.typ`
+    pub fn write(&mut self, path: &str, text: String) {
+        self.files
+            .loader_mut()
+            .write(Path::new(path), Bytes::new(text));
+
+        self.invalidate();
+    }
+
+    /// Adds a binary file (image, font, etc.)
+    pub fn attach(&mut self, path: &str, data: Vec<u8>) {
+        self.files
+            .loader_mut()
+            .write(Path::new(path), Bytes::new(data));
+
+        self.invalidate();
+    }
+
+    /// Sets the file compilation starts from, `main.typ` by default
+    ///
+    /// The path is given like in `write` and should be inside the root set by `set_root`.
+    /// A path outside of the root is looked up relative to the root instead.
+    /// A path leaving the project root is reported in `errors` and ignored.
+    pub fn set_main(&mut self, path: &str) {
+        self.switch_main(path);
+    }
+
+    /// Returns the path of the file compilation starts from, in the format of `list()`
+    ///
+    /// The path has no leading `/` and is the file that is actually compiled, so after
+    /// `set_root("src")` with the default main file it's `src/main.typ`.
+    pub fn get_main(&self) -> String {
+        let files = self.files.loader();
+        files.path(files.main)
+    }
+
+    /// Sets the folder typst treats as the project root, the top of the file list by default
+    ///
+    /// With `set_root("src")`, `#import "/lib/util.typ"` finds `src/lib/util.typ` and
+    /// the main file defaults to `src/main.typ`. Paths passed to `write`, `attach`, etc.
+    /// stay relative to the top of the file list.
+    /// A root leaving the file list is reported in `errors` and ignored.
+    pub fn set_root(&mut self, root: &str) {
+        if self.files.loader_mut().set_root(Path::new(root)).is_none() {
+            self.report(format!("{root}: path leaves the project root"));
+            return;
+        }
+
+        self.invalidate();
+    }
+
+    /// Replaces all occurrences of `find` with `replace` in the text files of the project
+    ///
+    /// The search is literal, not a regex. Binary files are skipped.
+    /// Returns the number of replacements made.
+    pub fn replace_all(&mut self, find: &str, replace: &str) -> usize {
+        if find.is_empty() {
+            return 0;
+        }
+
+        let mut count = 0;
+        for (path, data) in self.files.loader().entries() {
+            let path = PathBuf::from(path);
+            if !is_text(&path) {
+                continue;
+            }
+            let Ok(text) = std::str::from_utf8(&data) else {
+                continue;
+            };
+
+            let found = text.matches(find).count();
+            if found > 0 {
+                let replaced = text.replace(find, replace);
+                self.files.loader_mut().write(&path, Bytes::new(replaced));
+                count += found;
+            }
+        }
+
+        if count > 0 {
+            self.invalidate();
+        }
+
+        count
+    }
+
+    /// Returns the Unicode codepoints covered by a loaded font family
+    ///
+    /// Returns JSON `[[start, end], …]` with inclusive, sorted codepoint ranges,
+    /// merged over all faces of the family, or `null` if the family isn't loaded
+    pub fn glyph_coverage(&self, family: &str) -> String {
+        let mut codepoints = BTreeSet::new();
+        for index in self.book.select_family(&family.to_lowercase()) {
+            if let Some(info) = self.book.info(index) {
+                codepoints.extend(info.coverage.iter());
+            }
+        }
+
+        if codepoints.is_empty() {
+            return "null".into();
+        }
+
+        let mut ranges: Vec<[u32; 2]> = Vec::new();
+        for c in codepoints {
+            match ranges.last_mut() {
+                Some(range) if range[1] + 1 == c => range[1] = c,
+                _ => ranges.push([c, c]),
+            }
+        }
+
+        serde_json::to_string(&ranges).unwrap_or_default()
+    }
+
+    /// Returns the loaded font families that are built into typ-js, sorted
+    ///
+    /// These are typst's default fonts, the ones shipped with the typst CLI.
+    /// Families dropped by `optimize_for` aren't listed. Fonts added with `attach_font`
+    /// are in `user_fonts()` instead, even if they are the same family as a bundled one.
+    pub fn bundled_fonts(&self) -> Vec<String> {
+        self.font_families(true)
+    }
+
+    /// Returns the loaded font families that were added with `attach_font`, sorted
+    ///
+    /// A family is in both lists if the bundled and the attached fonts both have faces of it.
+    pub fn user_fonts(&self) -> Vec<String> {
+        self.font_families(false)
+    }
+
+    /// Returns the font families the document asks for that aren't loaded
+    ///
+    /// Compiles the document and collects typst's `unknown font family` warnings,
+    /// so families used only in code that doesn't run aren't reported.
+    /// Families are lowercase, as typst matches them case-insensitively.
+    pub fn required_fonts(&mut self) -> Vec<String> {
+        self.compile();
+        self.missing_families()
+    }
+
+    /// Sets a function that supplies fonts the document asks for but that aren't loaded
+    ///
+    /// `provider(family)` is called once per missing family, see `required_fonts`.
+    /// It returns the font as a `Uint8Array` to recompile with it right away, or
+    /// `undefined` to fetch it and `attach_font` it later:
+    ///
+    /// ```js
+    /// typ.set_font_provider((family) => {
+    ///   if (cache.has(family)) return cache.get(family)
+    ///   fetchFont(family).then((data) => {
+    ///     typ.attach_font(`fonts/${family}.ttf`, data)
+    ///     rerender()
+    ///   })
+    /// })
+    /// ```
+    ///
+    /// Calling this again, also with `undefined` to remove the provider,
+    /// asks for all missing families again.
+    pub fn set_font_provider(&mut self, provider: Option<Function>) {
+        self.font_provider = provider;
+        self.requested_fonts.clear();
+    }
+
+    /// Adds a font file, e.g. one bundled with a package
+    ///
+    /// The font is registered twice: as a file, so it can be referenced by path, and
+    /// in the font book, so text can use it by family name. With a `package` spec like
+    /// `@preview/cetz:0.3.0`, the file is staged in that package as with `stage_package`,
+    /// otherwise it's added to the project.
+    /// Returns the number of font faces found in the file.
+    pub fn attach_font(&mut self, path: &str, data: Vec<u8>, package: Option<String>) -> usize {
+        let data = Bytes::new(data);
+        match package {
+            Some(package) => {
+                let staged = package
+                    .parse::<PackageSpec>()
+                    .ok()
+                    .and_then(|spec| fs::stage_package(spec, Path::new(path), data.clone()));
+                if staged.is_none() {
+                    self.report(format!("invalid package file: {package} {path}"));
+                    return 0;
+                }
+            }
+            None => self.files.loader_mut().write(Path::new(path), data.clone()),
+        }
+
+        let added = self.add_fonts(data);
+        self.invalidate();
+        added
+    }
+
+    /// Makes `main` the main file and unloads all fonts it doesn't use
+    ///
+    /// This is destructive: dropped fonts are gone until added again, so documents
+    /// compiled afterwards may fall back to other fonts. Fonts attached with `attach_font`
+    /// stay available as files. Returns the number of dropped font faces.
+    /// If there are compile errors, sets the `errors` field and keeps all fonts.
+    pub fn optimize_for(&mut self, main: &str) -> usize {
+        if !self.switch_main(main) {
+            return 0;
+        }
+        let Some(doc) = self.compile() else {
+            return 0;
+        };
+
+        let mut used = HashSet::new();
+        for page in doc.pages() {
+            frames::walk(&page.frame, &mut |_, item| {
+                if let FrameItem::Text(text) = item {
+                    used.insert(text.font.clone());
+                }
+            });
+        }
+
+        let before = self.fonts.len();
+        let (fonts, bundled) = self
+            .fonts
+            .drain(..)
+            .zip(self.bundled.drain(..))
+            .filter(|(font, _)| used.contains(font))
+            .unzip();
+        self.fonts = fonts;
+        self.bundled = bundled;
+        self.book = LazyHash::new(FontBook::from_fonts(&self.fonts));
+
+        self.invalidate();
+        before - self.fonts.len()
+    }
+
+    /// Adds the bundled fonts again, e.g. after `optimize_for` dropped some of them
+    ///
+    /// Faces that are still loaded aren't added twice, and fonts attached with
+    /// `attach_font` stay. This brings back every bundled face, including the
+    /// monospace and math fonts a document might not have used before.
+    /// Returns the number of font faces added.
+    pub fn load_default_fonts(&mut self) -> usize {
+        let loaded: HashSet<usize> = self.bundled.iter().flatten().copied().collect();
+
+        let mut added = 0;
+        for (index, font) in bundled_fonts().enumerate() {
+            if !loaded.contains(&index) {
+                self.book.push(font.info().clone());
+                self.fonts.push(font);
+                self.bundled.push(Some(index));
+                added += 1;
+            }
+        }
+
+        self.invalidate();
+        added
+    }
+
+    /// Adds a file of a package, e.g. `stage_package("@preview/cetz:0.3.0", "lib.typ", data)`
+    ///
+    /// Staged packages are shared by all instances, so each package only needs to be
+    /// staged once per page load, and `#import "@preview/cetz:0.3.0"` works in all of them.
+    /// Any namespace works, see `stage_local_package` for private ones.
+    /// Returns `false` if the package spec or path is invalid.
+    pub fn stage_package(spec: &str, path: &str, data: Vec<u8>) -> bool {
+        let Ok(spec) = spec.parse::<PackageSpec>() else {
+            return false;
+        };
+
+        fs::stage_package(spec, Path::new(path), Bytes::new(data)).is_some()
+    }
+
+    /// Adds a file of a private package, so `#import "@local/name:version"` finds it
+    ///
+    /// The same as `stage_package("@local/name:version", path, data)`. Stage `typst.toml`
+    /// and the other files with paths relative to the package folder, e.g. `lib.typ`:
+    /// `check_imports` treats a package without `typst.toml` as missing.
+    /// Returns `false` if the name, version or path is invalid.
+    pub fn stage_local_package(name: &str, version: &str, path: &str, data: Vec<u8>) -> bool {
+        Self::stage_package(&format!("@local/{name}:{version}"), path, data)
+    }
+
+    /// Adds the contents of a remote file, so that e.g. `image("https://…")` can use it
+    ///
+    /// Typst can't fetch files, it treats URLs as paths like any other, which
+    /// typ-js maps back to the URL. Fetching is left to JS, using `missing_urls`:
+    ///
+    /// ```js
+    /// let svg = typ.svg()
+    /// while (typ.missing_urls().length > 0) {
+    ///   for (const url of typ.missing_urls()) {
+    ///     const data = await fetch(url).then(res => res.arrayBuffer())
+    ///     typ.attach_url(url, new Uint8Array(data))
+    ///   }
+    ///   svg = typ.svg()
+    /// }
+    /// ```
+    pub fn attach_url(&mut self, url: &str, data: Vec<u8>) {
+        self.files.loader_mut().attach_url(url, Bytes::new(data));
+
+        self.invalidate();
+    }
+
+    /// Returns the URLs the last compilation tried to read that weren't attached with `attach_url`
+    pub fn missing_urls(&self) -> Vec<String> {
+        self.files.loader().missing_urls()
+    }
+
+    /// Adds all files from a zip archive to the project
+    ///
+    /// The archive root becomes the project root, so the entry point
+    /// is the top-level `main.typ`. If every file is inside a single
+    /// top-level folder (as when zipping a project folder), that folder
+    /// is used as the root instead.
+    ///
+    /// Text files are checked to be valid UTF-8, everything else is added as-is.
+    /// Entries that fail to load are reported in `errors` and skipped.
+    pub fn load_zip(&mut self, data: Vec<u8>) {
+        self.errors = EcoVec::new();
+
+        let archive = match zip::Archive::new(data) {
+            Ok(archive) => archive,
+            Err(err) => {
+                self.report(format!("failed to open zip archive: {err}"));
+                return;
+            }
+        };
+
+        let paths: Vec<PathBuf> = archive
+            .entries()
+            .iter()
+            .filter(|entry| entry.is_file())
+            .filter_map(|entry| entry.enclosed_name())
+            .collect();
+        let prefix = common_folder(&paths).unwrap_or_default();
+
+        for entry in archive.entries() {
+            if !entry.is_file() {
+                continue;
+            }
+
+            let name = &entry.name;
+            let Some(path) = entry.enclosed_name() else {
+                self.report(format!("{name}: path leaves the project root"));
+                continue;
+            };
+            let path = path.strip_prefix(&prefix).unwrap_or(&path).to_path_buf();
+
+            let data = match archive.read(entry) {
+                Ok(data) => data,
+                Err(err) => {
+                    self.report(format!("{name}: {err}"));
+                    continue;
+                }
+            };
+
+            if is_text(&path) && std::str::from_utf8(&data).is_err() {
+                self.report(format!("{name}: file is not valid UTF-8"));
+                continue;
+            }
+
+            self.files.loader_mut().write(&path, Bytes::new(data));
+        }
+
+        self.invalidate();
+    }
+
+    /// Bundles all files into a zip archive, keeping their directory structure
+    ///
+    /// The archive can be loaded back with `load_zip`
+    pub fn export_zip(&mut self) -> Vec<u8> {
+        let mut archive = zip::Writer::new();
+
+        let entries = self.files.loader().entries();
+        for (path, data) in entries {
+            if archive.add(&path, &data).is_none() {
+                return self.record(Vec::new());
+            }
+        }
+
+        let zip = archive.finish().unwrap_or_default();
+        self.record(zip)
+    }
+
+    /// Outputs an SVG string with the rendered document
+    ///
+    /// For multiple pages this is one `<svg>` element per page, one after another,
+    /// which works as HTML but isn't a valid SVG file, see `svg_combined`.
+    /// If there are compile errors, sets the `errors` field and returns empty string
+    pub fn svg(&mut self) -> String {
+        let Some(doc) = self.compile() else {
+            return self.record(String::new());
+        };
+
+        let svg = doc
+            .pages()
+            .iter()
+            .map(|page| typst_svg::svg(page))
+            .collect();
+        self.record(svg)
+    }
+
+    /// Outputs a single valid SVG with all pages stacked vertically
+    ///
+    /// Pages are left-aligned below each other with `gap_pt` points between them and around
+    /// them (negative values count as 0), and the viewBox covers them all, as wide as the
+    /// widest page. Unlike `svg()`, the result can be used as a file, e.g. in `<img>`.
+    /// If there are compile errors, sets the `errors` field and returns empty string
+    pub fn svg_combined(&mut self, gap_pt: f64) -> String {
+        let Some(doc) = self.compile() else {
+            return self.record(String::new());
+        };
+
+        let svg = typst_svg::svg_merged(&doc, padding(Some(gap_pt)));
+        self.record(svg)
+    }
+
+    /// Outputs an SVG of the pages that compiled, even if others have errors
+    ///
+    /// Typst doesn't produce a partial document when compilation fails, the layout
+    /// only finishes once everything evaluated, so there is nothing to render and
+    /// this falls back to what `svg()` returns: the full document or an empty string.
+    /// If there are compile errors, sets the `errors` field and returns empty string
+    pub fn svg_best_effort(&mut self) -> String {
+        self.svg()
+    }
+
+    /// Compiles each of the given main files against the same set of files
+    ///
+    /// `format` is either `"svg"` or `"pdf"`. Returns an array with an object
+    /// `{ main, output, errors }` per main file, in the same order, where `output`
+    /// is what `svg()` or `pdf()` would return and `errors` what `errors()` would return
+    /// right after compiling that file. A main file leaving the project root gives empty
+    /// output and an error. The main file is restored afterwards.
+    pub fn render_all(&mut self, mains: Vec<String>, format: &str) -> JsValue {
+        let results = Array::new();
+        if format != "svg" && format != "pdf" {
+            self.errors = EcoVec::new();
+            self.last_output_size = 0;
+            self.report(format!("unknown format: {format}"));
+            return results.into();
+        }
+
+        let previous = self.files.loader().main_path().to_path_buf();
+        let mut size = 0;
+        for main in mains {
+            self.errors = EcoVec::new();
+            let output: JsValue = if !self.switch_main(&main) {
+                match format {
+                    "svg" => self.record(String::new()).into(),
+                    _ => Uint8Array::from(self.record(Vec::<u8>::new()).as_slice()).into(),
+                }
+            } else {
+                match format {
+                    "svg" => self.svg().into(),
+                    _ => Uint8Array::from(self.pdf().as_slice()).into(),
+                }
+            };
+            size += self.last_output_size;
+            let errors: Array = self.errors().into_iter().map(JsValue::from).collect();
+
+            let result = Object::new();
+            let _ = Reflect::set(&result, &"main".into(), &main.into());
+            let _ = Reflect::set(&result, &"output".into(), &output);
+            let _ = Reflect::set(&result, &"errors".into(), &errors);
+            results.push(&result);
+        }
+
+        self.files.loader_mut().set_main(&previous);
+        self.invalidate_output();
+
+        self.last_output_size = size;
+        results.into()
+    }
+
+    /// Compiles each of the given main files and outputs all their pages as one PDF
+    ///
+    /// Pages are in the order of `mains`, and each keeps its size, so documents with
+    /// different page sizes give a PDF with mixed page sizes.
+    /// The document metadata is taken from the first one.
+    /// `errors` collects the diagnostics of every document. If any document fails to
+    /// compile, returns empty array
+    pub fn pdf_merge(&mut self, mains: Vec<String>) -> Vec<u8> {
+        let previous = self.files.loader().main_path().to_path_buf();
+        let mut diagnostics = EcoVec::new();
+        let mut docs = Vec::new();
+        for main in &mains {
+            self.errors = EcoVec::new();
+            let doc = if self.switch_main(main) {
+                self.compile()
+            } else {
+                None
+            };
+            diagnostics.extend(self.errors.iter().cloned());
+            docs.extend(doc);
+        }
+
+        self.files.loader_mut().set_main(&previous);
+        self.invalidate_output();
+        self.errors = diagnostics;
+
+        if docs.len() < mains.len() {
+            return self.record(Vec::new());
+        }
+        let Some(first) = docs.first() else {
+            self.report("no documents to merge".into());
+            return self.record(Vec::new());
+        };
+
+        let pages = docs
+            .iter()
+            .flat_map(|doc| doc.pages().iter().cloned())
+            .collect();
+        let merged = PagedDocument::new(pages, first.info().clone());
+
+        match self.export_pdf(&merged, &self.pdf_options()) {
+            Ok(pdf) => self.record(pdf),
+            Err(errors) => {
+                self.errors = errors;
+                self.record(Vec::new())
+            }
+        }
+    }
+
+    /// Returns the paths of the files that were read during the last compilation,
+    /// in the format of `list()`
+    ///
+    /// Compiles first if anything changed since then. Only project files are listed,
+    /// not the files of the packages the document imports.
+    /// Files that are in the project but not listed here are unused by the document.
+    pub fn reachable_files(&mut self) -> Vec<String> {
+        self.compile();
+
+        let files = self.files.loader();
+        let mut paths: Vec<String> = self
+            .accessed
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|id| fs::is_project(**id))
+            .map(|id| files.path(*id))
+            .collect();
+        paths.sort();
+
+        paths
+    }
+
+    /// Returns the paths of the files that weren't read during the last compilation,
+    /// in the format of `list()`, the inverse of `reachable_files`
+    ///
+    /// Compiles first if anything changed since then. The main file is never listed.
+    /// If compilation fails, files after the error may not have been read yet and are
+    /// listed too. Fonts attached with `attach_font` are listed unless the document reads
+    /// them by path, since using a font by family name doesn't read its file.
+    pub fn unused_files(&mut self) -> Vec<String> {
+        let reachable: HashSet<String> = self.reachable_files().into_iter().collect();
+        let files = self.files.loader();
+        let main = files.path(files.main);
+
+        let mut paths: Vec<String> = self
+            .list()
+            .into_iter()
+            .filter(|path| *path != main && !reachable.contains(path))
+            .collect();
+        paths.sort();
+
+        paths
+    }
+
+    /// Outputs the same SVG as `svg()`, but with stable element ids
+    ///
+    /// The ids typst generates are renamed to `id0`, `id1`, … in order of appearance,
+    /// so the same input always produces byte-identical output that can be cached by hash.
+    /// The output can still change between typ-js releases or when fonts change.
+    pub fn svg_deterministic(&mut self) -> String {
+        let svg = svg::stable_ids(&self.svg());
+        self.record(svg)
+    }
+
+    /// Returns a fingerprint of everything the output depends on, as 32 hex digits
+    ///
+    /// Covers the contents and paths of all files and attached URLs, the main file and root,
+    /// the loaded fonts, the staged packages, the prelude settings (language, …), `sys.inputs`,
+    /// the date returned by `datetime.today()`, the PDF producer and the warnings-as-errors setting.
+    /// If the fingerprint matches an earlier one, the earlier output can be reused.
+    pub fn state_hash(&self) -> String {
+        let files = self.files.loader();
+        let mut entries = files.entries();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let hash = hash128(&(
+            entries,
+            files.urls(),
+            files.main_path(),
+            files.root(),
+            &self.book,
+            fs::packages_version(),
+            self.prelude.rules(),
+            &self.inputs,
+            self.today(None),
+            &self.pdf_producer,
+            self.warnings_as_errors,
+        ));
+
+        format!("{hash:032x}")
+    }
+
+    /// Outputs an SVG string like `svg()`, configured by an options object
+    ///
+    /// - `embed_fonts` (default `true`): when `false`, text is emitted as `<text>` elements
+    ///   referencing the font family instead of glyph outlines. This makes the SVG much smaller,
+    ///   but it only looks right if the page loads matching webfonts, and the browser's
+    ///   text shaping may differ slightly from typst's. The text is drawn after everything
+    ///   else, so it ends up above shapes and images and isn't clipped (e.g. by
+    ///   `box(clip: true)`), and gradient and tiling fills become black
+    /// - `unit` (default `"pt"`): the unit of the `width` and `height` of each page's `<svg>`.
+    ///   With `"px"`, they are converted from points at `ppi` pixels per inch
+    ///   (default 96, the CSS pixel, where 1pt is 4/3px). The viewBox stays in points,
+    ///   so only the displayed size changes, not the drawing
+    ///
+    /// If there are compile errors, sets the `errors` field and returns empty string
+    pub fn svg_with_options(&mut self, options: JsValue) -> String {
+        let option = |key: &str| Reflect::get(&options, &key.into()).ok();
+        let embed_fonts = option("embed_fonts")
+            .and_then(|value| value.as_bool())
+            .unwrap_or(true);
+        let ppi = match option("unit")
+            .and_then(|value| value.as_string())
+            .as_deref()
+        {
+            Some("px") => Some(
+                option("ppi")
+                    .and_then(|value| value.as_f64())
+                    .filter(|ppi| *ppi > 0.0)
+                    .unwrap_or(96.0),
+            ),
+            Some("pt") | None => None,
+            Some(unit) => {
+                self.report(format!("unknown SVG unit `{unit}`"));
+                return self.record(String::new());
+            }
+        };
+
+        let Some(doc) = self.compile() else {
+            return self.record(String::new());
+        };
+
+        let svg = doc
+            .pages()
+            .iter()
+            .map(|page| {
+                if embed_fonts {
+                    typst_svg::svg(page)
+                } else {
+                    svg::with_text_elements(page)
+                }
+            })
+            .collect();
+        let svg = match ppi {
+            Some(ppi) => svg::pixel_size(&svg, ppi),
+            None => svg,
+        };
+        self.record(svg)
+    }
+
+    /// Returns the paths of the image files read during the last compilation,
+    /// in the format of `list()`
+    ///
+    /// Images are recognized by their extension. Remote images attached with `attach_url`
+    /// are listed by their URL, images created from bytes in the document aren't files
+    /// and aren't listed. Compiles first if anything changed.
+    pub fn used_images(&mut self) -> Vec<String> {
+        self.reachable_files()
+            .into_iter()
+            .map(|path| fs::url_of(&path).unwrap_or(path))
+            .filter(|path| {
+                Path::new(path.split('?').next().unwrap_or(path))
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+            })
+            .collect()
+    }
+
+    /// Returns the number of pages in the document
+    ///
+    /// If there are compile errors, sets the `errors` field and returns 0
+    pub fn page_count(&mut self) -> usize {
+        self.compile().map_or(0, |doc| doc.pages().len())
+    }
+
+    /// Returns the height of all pages stacked vertically with `gap_pt` between them, in points
+    ///
+    /// One point is 1/72 inch, which is one SVG user unit in the page SVGs, so scale it like
+    /// their viewBox. Negative gaps count as 0.
+    /// If there are compile errors, sets the `errors` field and returns 0
+    pub fn total_height(&mut self, gap_pt: f64) -> f64 {
+        let Some(doc) = self.compile() else {
+            return 0.0;
+        };
+
+        let pages = doc.pages();
+        let heights: f64 = pages.iter().map(|page| page.frame.height().to_pt()).sum();
+        let gaps = pages.len().saturating_sub(1) as f64 * gap_pt.max(0.0);
+
+        heights + gaps
+    }
+
+    /// Outputs an SVG string with a single page of the rendered document
+    ///
+    /// The compiled document is kept until a file or setting changes,
+    /// so for large documents prefer calling this once per page over `svg()`:
+    ///
+    /// ```js
+    /// for (let i = 0; i < typ.page_count(); i++) {
+    ///   pages[i].innerHTML = typ.svg_page_into(i)
+    /// }
+    /// ```
+    ///
+    /// Returns empty string if the page doesn't exist or there are compile errors
+    pub fn svg_page_into(&mut self, index: usize) -> String {
+        let svg = self
+            .compile()
+            .and_then(|doc| doc.pages().get(index).map(typst_svg::svg))
+            .unwrap_or_default();
+        self.record(svg)
+    }
+
+    /// Outputs one SVG per page, in page order, e.g. as the frames of a slideshow
+    ///
+    /// Each entry is what `svg_page_into` returns for that page, a standalone `<svg>`,
+    /// so a player can show them one at a time. Slide packages like Polylux and Touying
+    /// already produce one page per step of an animation, so no transition information
+    /// is added. Unlike `svg()`, the pages aren't concatenated.
+    /// If there are compile errors, sets the `errors` field and returns empty array
+    pub fn svg_frames(&mut self) -> Vec<String> {
+        self.last_output_size = 0;
+        let Some(doc) = self.compile() else {
+            return Vec::new();
+        };
+
+        let frames: Vec<String> = doc.pages().iter().map(typst_svg::svg).collect();
+        self.last_output_size = frames.iter().map(String::len).sum();
+        frames
+    }
+
+    /// Returns the indices of the pages that changed since the last call
+    ///
+    /// A page counts as changed if the hash of its contents differs,
+    /// so the first call returns all pages.
+    /// Removed pages at the end aren't reported, compare with `page_count` for those.
+    /// If there are compile errors, sets the `errors` field and returns empty array
+    pub fn changed_pages(&mut self) -> Vec<usize> {
+        let Some(doc) = self.compile() else {
+            return Vec::new();
+        };
+
+        let hashes: Vec<u128> = doc.pages().iter().map(hash128).collect();
+        let changed = hashes
+            .iter()
+            .enumerate()
+            .filter(|(i, hash)| self.page_hashes.get(*i) != Some(hash))
+            .map(|(i, _)| i)
+            .collect();
+
+        self.page_hashes = hashes;
+        changed
+    }
+
+    /// Outputs the SVGs of the pages that changed since the last call, for live reload
+    ///
+    /// Outputs a JSON array of `{ "page", "svg" }`, detecting changes like `changed_pages`.
+    /// The first call returns all pages.
+    /// If there are compile errors, sets the `errors` field and returns `[]`
+    pub fn svg_diff(&mut self) -> String {
+        let Some(doc) = self.compile() else {
+            return self.record("[]".into());
+        };
+
+        let hashes: Vec<u128> = doc.pages().iter().map(hash128).collect();
+        let changed: Vec<_> = doc
+            .pages()
+            .iter()
+            .zip(&hashes)
+            .enumerate()
+            .filter(|(i, (_, hash))| self.svg_hashes.get(*i) != Some(hash))
+            .map(|(i, (page, _))| json!({ "page": i, "svg": typst_svg::svg(page) }))
+            .collect();
+
+        self.svg_hashes = hashes;
+        let json = serde_json::to_string(&changed).unwrap_or_default();
+        self.record(json)
+    }
+
+    /// Returns `[width, height]` in pixels of the page rendered at `ppi` pixels per inch,
+    /// without rendering it
+    ///
+    /// Like the renderer, each side is the size in points times `ppi / 72`,
+    /// rounded to the nearest pixel and at least 1.
+    /// If the page doesn't exist, `ppi` isn't positive or there are compile errors,
+    /// sets the `errors` field and returns empty array
+    pub fn pixel_dimensions(&mut self, index: usize, ppi: f32) -> Vec<u32> {
+        let Some(doc) = self.compile() else {
+            return Vec::new();
+        };
+        let Some(page) = doc.pages().get(index) else {
+            self.report(format!("page {index} doesn't exist"));
+            return Vec::new();
+        };
+        if !self.check_scale(ppi / 72.0) {
+            return Vec::new();
+        }
+
+        let (width, height) = raster::size(page.frame.size(), ppi / 72.0);
+
+        vec![width as u32, height as u32]
+    }
+
+    /// Outputs a single page as a lossless WebP image, rendered at `ppi` pixels per inch
+    ///
+    /// If the page doesn't exist, is too large to render at `ppi` or there are compile errors,
+    /// sets the `errors` field and returns empty array
+    pub fn webp_page(&mut self, index: usize, ppi: f32) -> Vec<u8> {
+        let Some(pixmap) = self.render_page(index, ppi) else {
+            return self.record(Vec::new());
+        };
+
+        let mut webp = Vec::new();
+        let encoded = WebPEncoder::new_lossless(&mut webp).write_image(
+            &raster::rgba(&pixmap),
+            pixmap.width(),
+            pixmap.height(),
+            ExtendedColorType::Rgba8,
+        );
+        if let Err(err) = encoded {
+            self.report(format!("failed to encode WebP: {err}"));
+            return self.record(Vec::new());
+        }
+
+        self.record(webp)
+    }
+
+    /// Renders all pages at `ppi` pixels per inch and hashes their pixels, for visual regression tests
+    ///
+    /// Returns a JSON array with one hash per page, as 16 hex digits. The hash is
+    /// 64-bit FNV-1a over the width and height (little-endian u32) followed by the
+    /// non-premultiplied RGBA pixels, so it's exact: any changed pixel changes it.
+    /// If a page is too large to render at `ppi` or there are compile errors,
+    /// sets the `errors` field and returns `[]`
+    pub fn render_hash(&mut self, ppi: f32) -> String {
+        let Some(doc) = self.compile() else {
+            return self.record("[]".into());
+        };
+
+        let mut hashes = Vec::new();
+        for page in doc.pages() {
+            let Some(pixmap) = self.rasterize(page, ppi / 72.0) else {
+                return self.record("[]".into());
+            };
+            hashes.push(format!("{:016x}", raster::fnv1a(&pixmap)));
+        }
+        let json = serde_json::to_string(&hashes).unwrap_or_default();
+
+        self.record(json)
+    }
+
+    /// Outputs a single page as a PNG cropped to its content, trimming the margins
+    ///
+    /// The crop is the bounding box of all text, shapes and images on the page, without stroke
+    /// thickness, grown by `padding_pt` points on every side
+    /// (0 if omitted, negative values count as 0). The page background fills the cropped area.
+    /// `compression` works like in `png_data_uri`.
+    /// If the page doesn't exist, is empty, is too large to render at `ppi` or there are
+    /// compile errors, sets the `errors` field and returns empty array
+    pub fn png_cropped_page(
+        &mut self,
+        index: usize,
+        ppi: f32,
+        padding_pt: Option<f64>,
+        compression: Option<u8>,
+    ) -> Vec<u8> {
+        let Some(doc) = self.compile() else {
+            return self.record(Vec::new());
+        };
+        let Some(page) = doc.pages().get(index) else {
+            self.report(format!("page {index} doesn't exist"));
+            return self.record(Vec::new());
+        };
+        let Some(rect) = frames::content_bounds(&page.frame) else {
+            self.report(format!("page {index} is empty"));
+            return self.record(Vec::new());
+        };
+
+        let mut cropped = page.clone();
+        cropped.frame = frames::crop(&page.frame, rect.pad(padding(padding_pt)));
+
+        let Some(pixmap) = self.rasterize(&cropped, ppi / 72.0) else {
+            return self.record(Vec::new());
+        };
+
+        let png = self.encode_png(pixmap, compression);
+        self.record(png)
+    }
+
+    /// Outputs the first page as a `data:image/png;base64,…` URI, rendered at `ppi` pixels per inch
+    ///
+    /// `compression` ranges from 0 to 9 like zlib levels, higher is smaller but slower
+    /// (6 if omitted, larger values count as 9). The encoder has three levels:
+    /// 0 to 3 is fast, for interactive previews, 4 to 6 the default and
+    /// 7 to 9 the best, for downloads.
+    /// If the page is too large to render at `ppi` or there are compile errors,
+    /// sets the `errors` field and returns empty string
+    pub fn png_data_uri(&mut self, ppi: f32, compression: Option<u8>) -> String {
+        let png = self.png(0, ppi, compression);
+        if png.is_empty() {
+            return self.record(String::new());
+        }
+
+        let uri = format!("data:image/png;base64,{}", BASE64_STANDARD.encode(png));
+        self.record(uri)
+    }
+
+    /// Outputs all pages as thumbnails in a grid, as one PNG, e.g. for a minimap
+    ///
+    /// Pages are rendered `thumb_width` pixels wide, keeping their aspect ratio, and laid
+    /// out left to right in rows of `columns` (at least 1), with 4 transparent pixels between
+    /// them. Each row is as tall as its tallest thumbnail, and thumbnails are aligned to the
+    /// top left of their cell, so pages of different sizes don't overlap. The last row may
+    /// be partly empty. `compression` works like in `png_data_uri`.
+    /// If `thumb_width` is 0, the image would be too large or there are compile errors,
+    /// sets the `errors` field and returns empty array
+    pub fn overview_png(
+        &mut self,
+        columns: u32,
+        thumb_width: u32,
+        compression: Option<u8>,
+    ) -> Vec<u8> {
+        const GAP: u32 = 4;
+
+        let Some(doc) = self.compile() else {
+            return self.record(Vec::new());
+        };
+        if thumb_width == 0 {
+            self.report("thumbnail width must be positive".into());
+            return self.record(Vec::new());
+        }
+
+        let columns = columns.max(1);
+        let mut thumbs = Vec::new();
+        for page in doc.pages() {
+            let scale = thumb_width as f32 / page.frame.width().to_f32().max(1.0);
+            let Some(thumb) = self.rasterize(page, scale) else {
+                return self.record(Vec::new());
+            };
+            thumbs.push(thumb);
+        }
+
+        let rows: Vec<&[Pixmap]> = thumbs.chunks(columns as usize).collect();
+        let heights: Vec<u32> = rows
+            .iter()
+            .map(|row| row.iter().map(|thumb| thumb.height()).max().unwrap_or(0))
+            .collect();
+        let width = columns.min(thumbs.len() as u32).max(1) * (thumb_width + GAP) - GAP;
+        let height = heights
+            .iter()
+            .map(|height| height + GAP)
+            .sum::<u32>()
+            .max(GAP + 1)
+            - GAP;
+
+        let Some(mut overview) = Pixmap::new(width, height) else {
+            self.report(format!("overview of {width}x{height} pixels is too large"));
+            return self.record(Vec::new());
+        };
+
+        let mut y = 0;
+        for (row, height) in rows.iter().zip(&heights) {
+            for (column, thumb) in row.iter().enumerate() {
+                let x = column as u32 * (thumb_width + GAP);
+                overview.draw_pixmap(
+                    x as i32,
+                    y as i32,
+                    thumb.as_ref(),
+                    &tiny_skia::PixmapPaint::default(),
+                    tiny_skia::Transform::identity(),
+                    None,
+                );
+            }
+            y += height + GAP;
+        }
+
+        let png = self.encode_png(overview, compression);
+        self.record(png)
+    }
+
+    /// Outputs the first page as a 1200x630 PNG, the Open Graph size for link previews
+    ///
+    /// The page is scaled to cover the whole image, cropped rather than letterboxed: it fills
+    /// the width, keeping its aspect ratio, and the top 630 pixels are kept, usually where
+    /// the title is. Pages wider than 1200:630 fill the height instead and are centered,
+    /// cutting off their sides. The background is opaque white wherever the page is
+    /// transparent, since sites showing the previews don't all handle transparency.
+    /// `compression` works like in `png_data_uri`.
+    /// If there are compile errors, sets the `errors` field and returns empty array
+    pub fn og_image(&mut self, compression: Option<u8>) -> Vec<u8> {
+        const WIDTH: u32 = 1200;
+        const HEIGHT: u32 = 630;
+
+        let Some(doc) = self.compile() else {
+            return self.record(Vec::new());
+        };
+        let Some(page) = doc.pages().first() else {
+            return self.record(Vec::new());
+        };
+
+        let size = page.frame.size();
+        let scale =
+            (WIDTH as f32 / size.x.to_f32().max(1.0)).max(HEIGHT as f32 / size.y.to_f32().max(1.0));
+
+        // Only the part that ends up in the image is rendered, however tall the page is
+        let width = Abs::pt(f64::from(WIDTH as f32 / scale));
+        let x = (size.x - width) / 2.0;
+        let visible = frames::Rect {
+            min: Point::with_x(x),
+            max: Point::new(x + width, Abs::pt(f64::from(HEIGHT as f32 / scale))),
+        };
+        let mut cropped = page.clone();
+        cropped.frame = frames::crop(&page.frame, visible);
+        let Some(rendered) = self.rasterize(&cropped, scale) else {
+            return self.record(Vec::new());
+        };
+
+        let Some(mut image) = Pixmap::new(WIDTH, HEIGHT) else {
+            return self.record(Vec::new());
+        };
+        image.fill(tiny_skia::Color::WHITE);
+        image.draw_pixmap(
+            0,
+            0,
+            rendered.as_ref(),
+            &tiny_skia::PixmapPaint::default(),
+            tiny_skia::Transform::identity(),
+            None,
+        );
+
+        let png = self.encode_png(image, compression);
+        self.record(png)
+    }
+
+    /// Returns how many milliseconds rasterizing each page at `ppi` pixels per inch takes
+    ///
+    /// Only rasterization is measured, compilation and layout happen before the first page.
+    /// Timed with `performance.now()`, falling back to `Date.now()` where it's unavailable.
+    /// If a page is too large to render at `ppi` or there are compile errors,
+    /// sets the `errors` field and returns empty array
+    pub fn page_timings(&mut self, ppi: f32) -> Vec<f64> {
+        let Some(doc) = self.compile() else {
+            return Vec::new();
+        };
+
+        let mut timings = Vec::new();
+        for page in doc.pages() {
+            let start = now();
+            if self.rasterize(page, ppi / 72.0).is_none() {
+                return Vec::new();
+            }
+            timings.push(now() - start);
+        }
+        timings
+    }
+
+    /// Outputs a single page as a JPEG image, rendered at `ppi` pixels per inch
+    ///
+    /// JPEG has no transparency, so the page is put onto a white background.
+    /// `quality` is clamped to 1..=100.
+    /// If the page doesn't exist, is too large to render at `ppi` or there are compile errors,
+    /// sets the `errors` field and returns empty array
+    pub fn jpeg_page(&mut self, index: usize, ppi: f32, quality: u8) -> Vec<u8> {
+        let Some(pixmap) = self.render_page(index, ppi) else {
+            return self.record(Vec::new());
+        };
+
+        let mut jpeg = Vec::new();
+        let encoded = JpegEncoder::new_with_quality(&mut jpeg, quality.clamp(1, 100)).write_image(
+            &raster::rgb_on_white(&pixmap),
+            pixmap.width(),
+            pixmap.height(),
+            ExtendedColorType::Rgb8,
+        );
+        if let Err(err) = encoded {
+            self.report(format!("failed to encode JPEG: {err}"));
+            return self.record(Vec::new());
+        }
+
+        self.record(jpeg)
+    }
+
+    /// Outputs a PDF with the rendered document as a UInt8Array
+    ///
+    /// If there are compile errors, sets the `errors` field and returns empty array
+    pub fn pdf(&mut self) -> Vec<u8> {
+        let Some(doc) = self.compile() else {
+            return self.record(Vec::new());
+        };
+        if let Some(pdf) = self.pdf.clone() {
+            return self.record(pdf);
+        }
+
+        match self.export_pdf(&doc, &self.pdf_options()) {
+            Ok(pdf) => {
+                self.pdf = Some(pdf.clone());
+                self.record(pdf)
+            }
+            Err(errors) => {
+                self.errors = errors;
+                self.record(Vec::new())
+            }
+        }
+    }
+
+    /// Outputs an accessible PDF that conforms to PDF/UA-1
+    ///
+    /// Needs typst 0.14 or later, which tags headings, paragraphs, lists, tables, figures,
+    /// links, the reading order and alternative texts. PDF/UA-1 also requires a document
+    /// title (`#set document(title: …)`) and an alternative text for every image.
+    /// If the document doesn't conform or there are compile errors, sets the `errors`
+    /// field and returns empty array
+    pub fn pdf_tagged(&mut self) -> Vec<u8> {
+        let Some(doc) = self.compile() else {
+            return self.record(Vec::new());
+        };
+        let Ok(standards) = PdfStandards::new(&[PdfStandard::Ua_1]) else {
+            self.report("PDF/UA-1 is not supported".into());
+            return self.record(Vec::new());
+        };
+
+        let options = PdfOptions {
+            tagged: true,
+            standards,
+            ..self.pdf_options()
+        };
+        match self.export_pdf(&doc, &options) {
+            Ok(pdf) => self.record(pdf),
+            Err(errors) => {
+                self.errors = errors;
+                self.record(Vec::new())
+            }
+        }
+    }
+
+    /// Outputs a PDF with an output intent, the color profile the document is meant for
+    ///
+    /// `intent` is `"none"`, like `pdf()`, or `"srgb"`, which exports as PDF/A-2b.
+    /// If the intent isn't supported or there are compile errors, sets the `errors` field
+    /// and returns empty array
+    pub fn pdf_with_output_intent(&mut self, intent: &str) -> Vec<u8> {
+        let standards = match intent {
+            "none" => return self.pdf(),
+            "srgb" => PdfStandards::new(&[PdfStandard::A_2b]),
+            _ => {
+                self.errors = EcoVec::new();
+                self.report(format!("unsupported output intent `{intent}`"));
+                return self.record(Vec::new());
+            }
+        };
+
+        let Some(doc) = self.compile() else {
+            return self.record(Vec::new());
+        };
+        let Ok(standards) = standards else {
+            self.report(format!("output intent `{intent}` is not supported"));
+            return self.record(Vec::new());
+        };
+
+        let options = PdfOptions {
+            standards,
+            ..self.pdf_options()
+        };
+        match self.export_pdf(&doc, &options) {
+            Ok(pdf) => self.record(pdf),
+            Err(errors) => {
+                self.errors = errors;
+                self.record(Vec::new())
+            }
+        }
+    }
+
+    /// Outputs one single-page PDF per page as an array of UInt8Arrays, in page order
+    ///
+    /// The document is compiled once, each PDF repeats the document metadata.
+    /// If there are compile errors, sets the `errors` field and returns empty array
+    pub fn pdf_per_page(&mut self) -> Vec<Uint8Array> {
+        self.last_output_size = 0;
+        let Some(doc) = self.compile() else {
+            return Vec::new();
+        };
+
+        let pdfs: SourceResult<Vec<Vec<u8>>> = (1..=doc.pages().len())
+            .filter_map(NonZeroUsize::new)
+            .map(|number| {
+                let options = PdfOptions {
+                    page_ranges: Some(PageRanges::new(vec![Some(number)..=Some(number)])),
+                    ..self.pdf_options()
+                };
+                self.export_pdf(&doc, &options)
+            })
+            .collect();
+        let pdfs = match pdfs {
+            Ok(pdfs) => pdfs,
+            Err(errors) => {
+                self.errors = errors;
+                return Vec::new();
+            }
+        };
+
+        self.last_output_size = pdfs.iter().map(Vec::len).sum();
+        pdfs.iter()
+            .map(|pdf| Uint8Array::from(pdf.as_slice()))
+            .collect()
+    }
+
+    /// Outputs a PDF whose pages are all exactly `width_mm` by `height_mm` millimeters,
+    /// whatever page size the document sets
+    ///
+    /// The content isn't scaled or reflowed: each page is placed at the top left corner
+    /// of the forced size. Content that doesn't fit is cut off at the right and bottom
+    /// edges, smaller pages leave the rest empty. To have the content laid out for the
+    /// size instead, set it with `#set page(…)` in the document.
+    /// If the size isn't positive or there are compile errors, sets the `errors` field
+    /// and returns empty array
+    pub fn pdf_with_page_size(&mut self, width_mm: f64, height_mm: f64) -> Vec<u8> {
+        let Some(doc) = self.compile() else {
+            return self.record(Vec::new());
+        };
+        if !(width_mm.is_finite() && width_mm > 0.0 && height_mm.is_finite() && height_mm > 0.0) {
+            self.report(format!("invalid page size: {width_mm}mm x {height_mm}mm"));
+            return self.record(Vec::new());
+        }
+
+        let area = frames::Rect {
+            min: Point::zero(),
+            max: Point::new(Abs::mm(width_mm), Abs::mm(height_mm)),
+        };
+        let pages = doc
+            .pages()
+            .iter()
+            .map(|page| Page {
+                frame: frames::crop(&page.frame, area),
+                ..page.clone()
+            })
+            .collect();
+        let resized = PagedDocument::new(pages, doc.info().clone());
+
+        match self.export_pdf(&resized, &self.pdf_options()) {
+            Ok(pdf) => self.record(pdf),
+            Err(errors) => {
+                self.errors = errors;
+                self.record(Vec::new())
+            }
+        }
+    }
+
+    /// Lists the fonts embedded in the PDF with their approximate size, largest first
+    ///
+    /// Returns JSON `[{ "font": "ABCDEF+Family", "bytes": number }]`, where `bytes` is
+    /// the compressed size of the embedded, subsetted font program, without the
+    /// surrounding PDF structures. Fonts stored in compressed object streams or drawn as
+    /// Type 3 fonts (e.g. color emoji) aren't listed.
+    /// If there are compile errors, sets the `errors` field and returns `[]`
+    pub fn font_size_report(&mut self) -> String {
+        let pdf = self.pdf();
+        let fonts: Vec<_> = pdf::font_sizes(&pdf)
+            .into_iter()
+            .map(|(font, bytes)| json!({ "font": font, "bytes": bytes }))
+            .collect();
+        let json = serde_json::to_string(&fonts).unwrap_or_default();
+
+        self.record(json)
+    }
+
+    /// Returns the size of the PDF in bytes
+    ///
+    /// The size is exact: the PDF is generated and kept,
+    /// so a following `pdf()` call doesn't have to generate it again.
+    /// If there are compile errors, sets the `errors` field and returns 0
+    pub fn estimate_pdf_size(&mut self) -> usize {
+        self.pdf().len()
+    }
+
+    /// Replaces the creator and producer of PDFs, which name the typst version by default
+    ///
+    /// An empty `producer` blanks them, omitting it restores the default. The original
+    /// values are blanked and the new ones are appended as an incremental update, so any
+    /// length and non-ASCII text work. Together with `set_deterministic`, PDFs neither
+    /// depend on the time nor name the typst version.
+    pub fn set_pdf_producer(&mut self, producer: Option<String>) {
+        self.pdf_producer = producer;
+        self.pdf = None;
+    }
+
+    /// Makes the output reproducible for the same input
+    ///
+    /// When on, `datetime.today()` always returns 1970-01-01
+    /// and PDFs are stamped with that date instead of having no creation date.
+    /// These are the only sources of nondeterminism, the layout itself
+    /// and SVG output are already reproducible.
+    pub fn set_deterministic(&mut self, on: bool) {
+        self.deterministic = on;
+        self.invalidate();
+    }
+
+    /// Returns the label of each page as shown by its numbering, e.g. `i`, `ii`, `1`
+    ///
+    /// Pages numbered by a function or without numbering get their plain page number.
+    /// If there are compile errors, sets the `errors` field and returns empty array
+    pub fn page_labels(&mut self) -> Vec<String> {
+        let Some(doc) = self.compile() else {
+            return Vec::new();
+        };
+
+        doc.pages()
+            .iter()
+            .map(|page| match &page.numbering {
+                Some(Numbering::Pattern(pattern)) => pattern.apply(&[page.number]).into(),
+                _ => page.number.to_string(),
+            })
+            .collect()
+    }
+
+    /// Returns the byte offset in its source file of the text, shape or image
+    /// closest to the given position on a page
+    ///
+    /// `x` and `y` are in points from the top left corner of the page.
+    /// Returns -1 if the page doesn't exist or has nothing that maps to source code.
+    /// If there are compile errors, sets the `errors` field and returns -1
+    pub fn offset_at(&mut self, page: usize, x: f64, y: f64) -> i64 {
+        let Some(doc) = self.compile() else {
+            return -1;
+        };
+        let Some(page) = doc.pages().get(page) else {
+            return -1;
+        };
+
+        let point = Point::new(Abs::pt(x), Abs::pt(y));
+        frames::nearest_span(&page.frame, point)
+            .and_then(|(span, offset)| {
+                let source = self.source(span.id()?).ok()?;
+                let range = source.range(span)?;
+                Some((range.start + usize::from(offset)).min(range.end) as i64)
+            })
+            .unwrap_or(-1)
+    }
+
+    /// Returns the languages of the text in the document, e.g. `["de", "en"]`
+    ///
+    /// These are the `lang` values of the laid out text, as ISO 639 codes without the
+    /// region, each listed once, sorted. Text typst didn't lay out, e.g. in unused
+    /// definitions, doesn't count, and text without a set language is `en`, typst's default.
+    /// If there are compile errors, sets the `errors` field and returns empty array
+    pub fn used_languages(&mut self) -> Vec<String> {
+        let Some(doc) = self.compile() else {
+            return Vec::new();
+        };
+
+        let mut langs = BTreeSet::new();
+        for page in doc.pages() {
+            frames::walk(&page.frame, &mut |_, item| {
+                if let FrameItem::Text(text) = item {
+                    langs.insert(text.lang.as_str().to_string());
+                }
+            });
+        }
+
+        langs.into_iter().collect()
+    }
+
+    /// Returns the text of each page, e.g. to build a per-page search index
+    ///
+    /// The text is in the order typst laid it out, with a line break between baselines.
+    /// If there are compile errors, sets the `errors` field and returns empty array
+    pub fn page_texts(&mut self) -> Vec<String> {
+        let Some(doc) = self.compile() else {
+            return Vec::new();
+        };
+
+        doc.pages()
+            .iter()
+            .map(|page| frames::text(&page.frame))
+            .collect()
+    }
+
+    /// Finds the occurrences of `query` in the document, e.g. to highlight search results
+    ///
+    /// Outputs a JSON array of `{ "page", "x", "y", "width", "height" }` in points, as in
+    /// `offset_at`. A match can't span a line break or a change of font or style.
+    /// If there are compile errors, sets the `errors` field and returns `[]`
+    pub fn find_text(&mut self, query: &str, case_sensitive: bool) -> String {
+        let Some(doc) = self.compile() else {
+            return "[]".into();
+        };
+
+        let matches: Vec<_> = doc
+            .pages()
+            .iter()
+            .enumerate()
+            .flat_map(|(i, page)| {
+                frames::find_text(&page.frame, query, case_sensitive)
+                    .into_iter()
+                    .map(move |rect| {
+                        let size = rect.size();
+                        json!({
+                            "page": i,
+                            "x": rect.min.x.to_pt(),
+                            "y": rect.min.y.to_pt(),
+                            "width": size.x.to_pt(),
+                            "height": size.y.to_pt(),
+                        })
+                    })
+            })
+            .collect();
+
+        serde_json::to_string(&matches).unwrap_or_default()
+    }
+
+    /// Lists the glyphs on a page with their positions, e.g. for text selection over a rendered page
+    ///
+    /// Outputs a JSON array of `{ "x", "y", "advance", "ascent", "descent", "size", "cluster" }`
+    /// in points, with `x` and `y` on the baseline as in `offset_at`, or `[]` if `page` is out of range.
+    /// If there are compile errors, sets the `errors` field and returns `[]`
+    pub fn glyphs(&mut self, page: usize) -> String {
+        let Some(doc) = self.compile() else {
+            return "[]".into();
+        };
+        let Some(page) = doc.pages().get(page) else {
+            return "[]".into();
+        };
+
+        let glyphs: Vec<_> = frames::glyphs(&page.frame)
+            .into_iter()
+            .map(|glyph| {
+                json!({
+                    "x": glyph.origin.x.to_pt(),
+                    "y": glyph.origin.y.to_pt(),
+                    "advance": glyph.advance.to_pt(),
+                    "ascent": glyph.ascent.to_pt(),
+                    "descent": glyph.descent.to_pt(),
+                    "size": glyph.size.to_pt(),
+                    "cluster": glyph.cluster,
+                })
+            })
+            .collect();
+
+        serde_json::to_string(&glyphs).unwrap_or_default()
+    }
+
+    /// Lists the elements at the given position on a page, innermost first, e.g. for context menus
+    ///
+    /// Outputs a JSON array of `{ "kind", "label", "x", "y", "width", "height" }` in points,
+    /// as in `offset_at`. Only elements typst tracks in the layout are found, e.g. headings,
+    /// figures, links and labeled content.
+    /// If there are compile errors, sets the `errors` field and returns `[]`
+    pub fn elements_at(&mut self, page: usize, x: f64, y: f64) -> String {
+        let Some(doc) = self.compile() else {
+            return "[]".into();
+        };
+        let Some(page) = doc.pages().get(page) else {
+            return "[]".into();
+        };
+
+        let point = Point::new(Abs::pt(x), Abs::pt(y));
+        let elements: Vec<_> = frames::elements_at(&page.frame, point)
+            .into_iter()
+            .map(|(content, rect)| {
+                let size = rect.size();
+                json!({
+                    "kind": content.elem().name(),
+                    "label": content.label().map(|label| label.resolve().as_str().to_string()),
+                    "x": rect.min.x.to_pt(),
+                    "y": rect.min.y.to_pt(),
+                    "width": size.x.to_pt(),
+                    "height": size.y.to_pt(),
+                })
+            })
+            .collect();
+
+        serde_json::to_string(&elements).unwrap_or_default()
+    }
+
+    /// Describes the colors used in the document, to pick PDF export settings
+    ///
+    /// Outputs JSON `{ "spaces": string[], "gradients": bool, "tilings": bool }`, where
+    /// `spaces` are the color spaces of text, shape and gradient colors: `"srgb"`,
+    /// `"linearrgb"`, `"cmyk"`, `"d65gray"`, `"oklab"`, `"oklch"`, `"hsl"` or `"hsv"`.
+    /// Colors inside images aren't inspected.
+    /// If there are compile errors, sets the `errors` field and returns `null`
+    pub fn color_info(&mut self) -> String {
+        let Some(doc) = self.compile() else {
+            return "null".into();
+        };
+
+        let mut spaces = Vec::new();
+        let mut gradients = false;
+        let mut tilings = false;
+        for page in doc.pages() {
+            frames::paints(&page.frame, &mut |paint| {
+                let space = match paint {
+                    Paint::Solid(color) => color.space(),
+                    Paint::Gradient(gradient) => {
+                        gradients = true;
+                        gradient.space()
+                    }
+                    Paint::Tiling(_) => {
+                        tilings = true;
+                        return;
+                    }
+                };
+
+                let name = match space {
+                    ColorSpace::Oklab => "oklab",
+                    ColorSpace::Oklch => "oklch",
+                    ColorSpace::Srgb => "srgb",
+                    ColorSpace::D65Gray => "d65gray",
+                    ColorSpace::LinearRgb => "linearrgb",
+                    ColorSpace::Hsl => "hsl",
+                    ColorSpace::Hsv => "hsv",
+                    ColorSpace::Cmyk => "cmyk",
+                };
+                if !spaces.contains(&name) {
+                    spaces.push(name);
+                }
+            });
+        }
+
+        json!({ "spaces": spaces, "gradients": gradients, "tilings": tilings }).to_string()
+    }
+
+    /// Outputs an SVG of just the element with the given label, e.g. `fig` for `<fig>`
+    ///
+    /// The page the element is on is cropped to the element's bounding box,
+    /// grown by `padding_pt` points on every side (0 if omitted, negative values count as 0),
+    /// so other content overlapping that box is visible too.
+    /// If the label isn't found or there are compile errors, sets the `errors` field and returns empty string
+    pub fn svg_element(&mut self, label: &str, padding_pt: Option<f64>) -> String {
+        let padding = padding(padding_pt);
+        let Some(doc) = self.compile() else {
+            return self.record(String::new());
+        };
+
+        let found = Label::new(PicoStr::intern(label)).and_then(|label| {
+            doc.pages().iter().find_map(|page| {
+                frames::element_bounds(&page.frame, label)
+                    .map(|rect| frames::crop(&page.frame, rect.pad(padding)))
+            })
+        });
+        let Some(frame) = found else {
+            self.report(format!("label `<{label}>` does not exist in the document"));
+            return self.record(String::new());
+        };
+
+        let svg = typst_svg::svg_frame(&frame);
+        self.record(svg)
+    }
+
+    /// Outputs an SVG of just the math formula `expr`, e.g. `sum_(i=1)^n i`
+    ///
+    /// The formula is written as `$expr$`, or as the block equation `$ expr $`
+    /// (without numbering) if `display` is true. It's compiled on its
+    /// own with the fonts and text defaults of this instance (language, font, size,
+    /// direction and leading), but none of its files, page settings, watermark or
+    /// `set_prelude` code. The SVG is cropped to the formula's glyphs and shapes, with
+    /// the glyphs measured from ascender to descender of their font so the formula's
+    /// height doesn't depend on the letters in it.
+    /// The formula's baseline is available from `last_baseline()` afterwards.
+    /// If the formula is invalid, sets the `errors` field without source locations and
+    /// returns empty string
+    pub fn render_math(&mut self, expr: &str, display: bool) -> String {
+        // The metadata marks where the formula's baseline is. After a block
+        // equation it would be on the next line, so it goes into the equation
+        let marker = "#metadata(none)<typ-js-baseline>";
+        let math = if display {
+            format!("$ #[{marker}] {expr} $")
+        } else {
+            format!("${expr}${marker}")
+        };
+        let source = format!(
+            "#set page(width: auto, height: auto, margin: 0pt, fill: none)\n\
+             {math}\n"
+        );
+
+        let mut typ = self.snapshot();
+        typ.files = FileStore::new(fs::FS::new());
+        // A watermark, page settings or prelude code would end up in the crop
+        typ.prelude = self.prelude.text_only();
+        typ.write("main.typ", source);
+        let compiled = typ.compile();
+
+        // The spans point into the other instance's files
+        self.errors = typ
+            .errors
+            .iter()
+            .map(|err| SourceDiagnostic {
+                span: Span::detached(),
+                ..err.clone()
+            })
+            .collect();
+        self.last_baseline = 0.0;
+        let Some(page) = compiled.as_ref().and_then(|doc| doc.pages().first()) else {
+            return self.record(String::new());
+        };
+
+        let rect = frames::content_bounds(&page.frame).unwrap_or(frames::Rect {
+            min: Point::zero(),
+            max: page.frame.size().to_point(),
+        });
+        let marker = Label::new(PicoStr::intern("typ-js-baseline"))
+            .and_then(|label| frames::element_position(&page.frame, label));
+        if let Some(baseline) = marker {
+            self.last_baseline = (baseline.y - rect.min.y).to_pt();
+        }
+
+        let svg = typst_svg::svg_frame(&frames::crop(&page.frame, rect));
+        self.record(svg)
+    }
+
+    /// Returns the baseline of the last `render_math` formula, in points from the top of its SVG
+    ///
+    /// To align the formula with surrounding text, shift it down by the part below
+    /// the baseline, e.g. `vertical-align: -(height - baseline)` in CSS, with the
+    /// SVG height and baseline at the same scale. Parts of the formula can sit below
+    /// the baseline (descenders, subscripts, fraction denominators).
+    /// Returns 0 if the last formula failed to compile or there was none.
+    pub fn last_baseline(&self) -> f64 {
+        self.last_baseline
+    }
+
+    /// Checks the typst files used by the document for common authoring mistakes
+    ///
+    /// Returns a JSON array of `{ "rule", "message", "file", "line", "column", "start", "end" }`
+    /// with zero-based line and column and a byte range into the file. Rules:
+    /// - `unused-import`: a name imported with `#import "…": name` but never used in that file
+    /// - `unreferenced-label`: a label nothing refers to with `@name` or `<name>`
+    /// - `undefined-reference`: `@name` without a matching label,
+    ///   skipped if the project has a bibliography since `@key` may be a citation
+    ///
+    /// The checks work on the syntax, so they also run if compilation fails.
+    /// Only project files are checked, not the sources of imported packages.
+    pub fn lint(&mut self) -> String {
+        self.compile();
+
+        let ids: Vec<FileId> = self.accessed.lock().unwrap().iter().copied().collect();
+        let mut sources: Vec<Source> = ids
+            .into_iter()
+            .filter(|id| fs::is_project(*id))
+            .filter_map(|id| self.source(id).ok())
+            .collect();
+        sources.sort_by_key(|source| self.files.loader().path(source.id()));
+
+        let findings: Vec<_> = lint::RULES
+            .iter()
+            .flat_map(|rule| rule(&sources))
+            .map(|finding| {
+                let loc = diag::locate(self, self.files.loader(), finding.span);
+                json!({
+                    "rule": finding.rule,
+                    "message": finding.message,
+                    "file": loc.as_ref().map(|loc| &loc.path),
+                    "line": loc.as_ref().map(|loc| loc.line),
+                    "column": loc.as_ref().map(|loc| loc.column),
+                    "start": loc.as_ref().map(|loc| loc.range.start),
+                    "end": loc.as_ref().map(|loc| loc.range.end),
+                })
+            })
+            .collect();
+
+        serde_json::to_string(&findings).unwrap_or_default()
+    }
+
+    /// Returns the main file with the local files it includes and imports inlined,
+    /// as a single self-contained source, e.g. to paste into the typst web app
+    ///
+    /// - `#include "a.typ"` becomes the contents of `a.typ` in a content block `#[…]`,
+    ///   so its set rules stay scoped like with the include
+    /// - `#import "a.typ": …` becomes the contents of `a.typ`, the first time it's
+    ///   imported in a scope, and is dropped after that. All of its definitions are
+    ///   inlined, not just the imported ones, and any content it shows appears where it
+    ///   was imported
+    ///
+    /// Package, whole-module, renaming, cyclic and missing imports stay as they are.
+    /// Doesn't compile, so it also works for documents with errors.
+    pub fn flatten(&self) -> String {
+        let files = self.files.loader();
+        let read = |path: &Path| {
+            let data = files.read(path)?;
+            String::from_utf8(data.to_vec()).ok()
+        };
+        let main = PathBuf::from(files.path(files.main));
+
+        imports::flatten(
+            &main,
+            &read,
+            files.root(),
+            &mut Vec::new(),
+            &mut HashSet::new(),
+        )
+        .unwrap_or_default()
+    }
+
+    /// Lists the bibliography entries the document cites, e.g. for a references panel
+    ///
+    /// Outputs a JSON array of `{ "key", "title", "authors", "year" }` in order of first citation,
+    /// with `null` for missing fields. Returns `[]` without a `#bibliography`.
+    /// If there are compile errors, sets the `errors` field and returns `[]`
+    pub fn bibliography(&mut self) -> String {
+        let Some(doc) = self.compile() else {
+            return "[]".into();
+        };
+
+        let introspector = doc.introspector();
+        let bibliographies: Vec<_> = introspector
+            .query(&Selector::Elem(BibliographyElem::ELEM, None))
+            .iter()
+            .filter_map(|content| content.to_packed::<BibliographyElem>().cloned())
+            .collect();
+
+        let mut seen = HashSet::new();
+        let entries: Vec<_> = introspector
+            .query(&Selector::Elem(CiteElem::ELEM, None))
+            .iter()
+            .filter_map(|content| content.to_packed::<CiteElem>())
+            .filter(|cite| seen.insert(cite.key))
+            .filter_map(|cite| {
+                let entry = bibliographies
+                    .iter()
+                    .find_map(|bib| bib.sources.derived.get(cite.key))?;
+                let authors: Vec<String> = entry
+                    .authors()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|person| match &person.given_name {
+                        Some(given) => format!("{given} {}", person.name),
+                        None => person.name.clone(),
+                    })
+                    .collect();
+
+                Some(json!({
+                    "key": cite.key.resolve().as_str(),
+                    "title": entry.title().map(|title| title.value.to_string()),
+                    "authors": authors,
+                    "year": entry.date().map(|date| date.year),
+                }))
+            })
+            .collect();
+
+        serde_json::to_string(&entries).unwrap_or_default()
+    }
+
+    /// Returns how many elements of a kind the document has, e.g. for document statistics
+    ///
+    /// Supported kinds:
+    /// - `heading`, `figure`, `equation` (block and inline), `footnote`
+    /// - `table` and `image`: figures of that kind, so tables outside of a figure aren't counted
+    /// - `ref` and `cite`: references and citations, `@key` counts as one or the other
+    ///
+    /// Unknown kinds are reported in `errors` and return 0.
+    /// If there are compile errors, sets the `errors` field and returns 0
+    pub fn count(&mut self, kind: &str) -> usize {
+        let (elem, figure_kind) = match kind {
+            "heading" => (HeadingElem::ELEM, None),
+            "figure" => (FigureElem::ELEM, None),
+            "table" => (FigureElem::ELEM, Some(TableElem::ELEM)),
+            "image" => (FigureElem::ELEM, Some(ImageElem::ELEM)),
+            "equation" => (EquationElem::ELEM, None),
+            "footnote" => (FootnoteElem::ELEM, None),
+            "ref" => (RefElem::ELEM, None),
+            "cite" => (CiteElem::ELEM, None),
+            _ => {
+                self.report(format!("unknown element kind `{kind}`"));
+                return 0;
+            }
+        };
+
+        let Some(doc) = self.compile() else {
+            return 0;
+        };
+
+        let elements = doc.introspector().query(&Selector::Elem(elem, None));
+        match figure_kind {
+            Some(figure_kind) => elements
+                .iter()
+                .filter(|figure| figure.get_by_name("kind").ok() == Some(figure_kind.into_value()))
+                .count(),
+            None => elements.len(),
+        }
+    }
+
+    /// Checks that the files and packages the document imports and includes exist,
+    /// without compiling it
+    ///
+    /// Outputs a JSON array of `{ "message", "file", "line", "column", "start", "end" }`
+    /// like `lint()`, one per missing file or package. Paths computed at runtime aren't checked.
+    pub fn check_imports(&mut self) -> String {
+        let files = self.files.loader();
+        let mut problems = Vec::new();
+        let mut done = HashSet::new();
+        let mut queue = vec![PathBuf::from(files.path(files.main))];
+        while let Some(path) = queue.pop() {
+            if !done.insert(path.clone()) {
+                continue;
+            }
+            let Some(text) = files
+                .read(&path)
+                .and_then(|data| String::from_utf8(data.to_vec()).ok())
+            else {
+                continue;
+            };
+
+            let source = Source::new(files.id(&path), text);
+            for (span, target) in imports::directives(source.root()) {
+                let problem = if target.starts_with('@') {
+                    match target.parse::<PackageSpec>() {
+                        Ok(spec) if fs::has_package(&spec) => None,
+                        Ok(spec) => Some(format!("package {spec} is not staged")),
+                        Err(err) => Some(format!("invalid package {target}: {err}")),
+                    }
+                } else {
+                    let next = imports::resolve(&path, &target, files.root());
+                    if files.read(&next).is_some() {
+                        queue.push(next);
+                        None
+                    } else {
+                        Some(format!("file not found: {}", next.to_string_lossy()))
+                    }
+                };
+
+                if let Some(message) = problem {
+                    let loc = diag::locate_in(source.clone(), files, span);
+                    problems.push(json!({
+                        "message": message,
+                        "file": loc.as_ref().map(|loc| &loc.path),
+                        "line": loc.as_ref().map(|loc| loc.line),
+                        "column": loc.as_ref().map(|loc| loc.column),
+                        "start": loc.as_ref().map(|loc| loc.range.start),
+                        "end": loc.as_ref().map(|loc| loc.range.end),
+                    }));
+                }
+            }
+        }
+
+        serde_json::to_string(&problems).unwrap_or_default()
+    }
+
+    /// Lists the references in the document and where they point to, to check links
+    ///
+    /// Outputs a JSON array of `{ "label", "kind", "exists", "page", "ref_page", "file",
+    /// "line", "column" }` in document order, with one-based pages. `kind` is `"ref"` for
+    /// a reference to a label and `"cite"` for a citation of a bibliography entry, which
+    /// `exists` if a `#bibliography` has the key and has `page: null`.
+    /// If there are compile errors, sets the `errors` field and returns `[]`
+    pub fn references(&mut self) -> String {
+        let Some(doc) = self.compile() else {
+            return "[]".into();
+        };
+
+        let introspector = doc.introspector();
+        let bibliographies: Vec<_> = introspector
+            .query(&Selector::Elem(BibliographyElem::ELEM, None))
+            .iter()
+            .filter_map(|content| content.to_packed::<BibliographyElem>().cloned())
+            .collect();
+        let references: Vec<_> = introspector
+            .query(&Selector::Elem(RefElem::ELEM, None))
+            .iter()
+            .filter_map(|content| {
+                let reference = content.to_packed::<RefElem>()?;
+                let target = introspector.query_label(reference.target).ok();
+                let page = target
+                    .and_then(|target| target.location())
+                    .map(|location| introspector.page(location));
+                let ref_page = content
+                    .location()
+                    .map(|location| introspector.page(location));
+                let loc = diag::locate(self, self.files.loader(), content.span());
+                let exists = target.is_some()
+                    || bibliographies
+                        .iter()
+                        .any(|bib| bib.sources.derived.get(reference.target).is_some());
+
+                Some(json!({
+                    "label": reference.target.resolve().as_str(),
+                    "kind": if target.is_some() { "ref" } else { "cite" },
+                    "exists": exists,
+                    "page": page,
+                    "ref_page": ref_page,
+                    "file": loc.as_ref().map(|loc| &loc.path),
+                    "line": loc.as_ref().map(|loc| loc.line),
+                    "column": loc.as_ref().map(|loc| loc.column),
+                }))
+            })
+            .collect();
+
+        serde_json::to_string(&references).unwrap_or_default()
+    }
+
+    /// Lists the characters that were rendered without a glyph (tofu)
+    ///
+    /// Returns a JSON array of `{ "char": "…", "font_tried": "family" }`,
+    /// one entry per character and font, empty if everything resolved.
+    /// If there are compile errors, sets the `errors` field and returns `[]`
+    pub fn missing_glyphs(&mut self) -> String {
+        let Some(doc) = self.compile() else {
+            return "[]".into();
+        };
+
+        let mut missing = Vec::<(String, String)>::new();
+        for page in doc.pages() {
+            frames::walk(&page.frame, &mut |_, item| {
+                let FrameItem::Text(text) = item else {
+                    return;
+                };
+
+                for glyph in text.glyphs.iter().filter(|glyph| glyph.id == 0) {
+                    let entry = (
+                        text.text[glyph.range()].to_string(),
+                        text.font.info().family.clone(),
+                    );
+                    if !missing.contains(&entry) {
+                        missing.push(entry);
+                    }
+                }
+            });
+        }
+
+        let missing: Vec<_> = missing
+            .into_iter()
+            .map(|(ch, font)| json!({ "char": ch, "font_tried": font }))
+            .collect();
+        serde_json::to_string(&missing).unwrap_or_default()
+    }
+}
+
+impl TypJs {
+    /// The fixed date used in deterministic mode
+    fn epoch() -> Datetime {
+        Datetime::from_ymd(1970, 1, 1).unwrap()
+    }
+
+    fn pdf_options(&self) -> PdfOptions<'static> {
+        PdfOptions {
+            timestamp: self
+                .deterministic
+                .then(|| Timestamp::new_utc(Self::epoch())),
+            ..PdfOptions::default()
+        }
+    }
+
+    /// Exports a PDF, applying the settings typst doesn't have options for
+    fn export_pdf(&self, doc: &PagedDocument, options: &PdfOptions) -> SourceResult<Vec<u8>> {
+        let mut pdf = typst_pdf::pdf(doc, options)?;
+        if let Some(producer) = &self.pdf_producer
+            && pdf::set_producer(&mut pdf, producer).is_none()
+        {
+            let message = format!("failed to set the PDF producer to \"{producer}\"");
+            let error = SourceDiagnostic::error(Span::detached(), message)
+                .with_hint("omit it to keep the default");
+            return Err(EcoVec::from([error]));
+        }
+
+        Ok(pdf)
+    }
+
+    /// Builds the file that applies the prelude and includes the main file,
+    /// so that the main file itself and its spans stay untouched
+    fn build_wrapper(&self) -> Option<Source> {
+        let rules = self.prelude.rules();
+        if rules.is_empty() {
+            return None;
+        }
+
+        let files = self.files.loader();
+        let main = files.main.vpath().get_with_slash();
+        let text = format!("{rules}#include {}\n", prelude::string(main));
+
+        Some(Source::new(files.prelude, text))
+    }
+
+    /// Rebuilds the standard library with the current `sys.inputs`
+    fn update_library(&mut self) {
+        let inputs: Dict = self
+            .inputs
+            .iter()
+            .map(|(key, value)| (key.as_str().into(), value.clone().into_value()))
+            .collect();
+
+        self.lib = LazyHash::new(Library::builder().with_inputs(inputs).build());
+        self.invalidate();
+    }
+
+    /// Registers the faces of a font file in the font book, returns how many there are
+    fn add_fonts(&mut self, data: Bytes) -> usize {
+        let mut added = 0;
+        for font in Font::iter(data) {
+            self.book.push(font.info().clone());
+            self.fonts.push(font);
+            self.bundled.push(None);
+            added += 1;
+        }
+
+        added
+    }
+
+    /// The font families typst warned about in the last compilation, lowercase
+    fn missing_families(&self) -> Vec<String> {
+        let mut families = Vec::new();
+        for err in &self.errors {
+            if let Some(family) = err.message.strip_prefix("unknown font family: ") {
+                let family = family.to_lowercase();
+                if !families.contains(&family) {
+                    families.push(family);
+                }
+            }
+        }
+
+        families
+    }
+
+    /// Asks the font provider for missing families, returns whether it supplied any
+    fn provide_fonts(&mut self) -> bool {
+        let Some(provider) = self.font_provider.clone() else {
+            return false;
+        };
+
+        let mut added = 0;
+        for family in self.missing_families() {
+            if !self.requested_fonts.insert(family.clone()) {
+                continue;
+            }
+
+            let data = provider
+                .call1(&JsValue::NULL, &family.as_str().into())
+                .ok()
+                .and_then(|data| data.dyn_into::<Uint8Array>().ok());
+            if let Some(data) = data {
+                added += self.add_fonts(Bytes::new(data.to_vec()));
+            }
+        }
+
+        if added > 0 {
+            self.invalidate();
+        }
+        added > 0
+    }
+
+    /// The sorted families of the loaded fonts that are or aren't built in
+    fn font_families(&self, bundled: bool) -> Vec<String> {
+        let families: BTreeSet<String> = self
+            .fonts
+            .iter()
+            .zip(&self.bundled)
+            .filter(|(_, index)| index.is_some() == bundled)
+            .map(|(font, _)| font.info().family.clone())
+            .collect();
+
+        families.into_iter().collect()
+    }
+
+    /// Remembers the size of an output for `last_output_size`
+    fn record<T: AsRef<[u8]>>(&mut self, output: T) -> T {
+        self.last_output_size = output.as_ref().len();
+        output
+    }
+
+    /// Adds an error that isn't tied to a source location
+    fn report(&mut self, message: String) {
+        self.errors
+            .push(SourceDiagnostic::error(Span::detached(), message));
+    }
+
+    /// Makes `path` the main file, returns `false` and reports it in `errors`
+    /// if the path leaves the project root
+    fn switch_main(&mut self, path: &str) -> bool {
+        if self.files.loader_mut().set_main(Path::new(path)).is_none() {
+            self.report(format!("{path}: path leaves the project root"));
+            return false;
+        }
+
+        self.invalidate_output();
+        true
+    }
+
+    /// Drops everything derived from the files and settings
+    fn invalidate(&mut self) {
+        self.files.reset();
+        self.invalidate_output();
+    }
+
+    /// Drops the compiled output, but keeps the parsed files
+    fn invalidate_output(&mut self) {
+        self.document = None;
+        self.pdf = None;
+    }
+
+    /// Renders a single page to PNG, empty on failure
+    fn png(&mut self, index: usize, ppi: f32, compression: Option<u8>) -> Vec<u8> {
+        let Some(pixmap) = self.render_page(index, ppi) else {
+            return Vec::new();
+        };
+
+        self.encode_png(pixmap, compression)
+    }
+
+    /// Encodes a pixmap as PNG at a zlib-like compression level, empty on failure
+    fn encode_png(&mut self, pixmap: Pixmap, compression: Option<u8>) -> Vec<u8> {
+        let compression = match compression.unwrap_or(6) {
+            0..=3 => CompressionType::Fast,
+            4..=6 => CompressionType::Default,
+            _ => CompressionType::Best,
+        };
+
+        let mut png = Vec::new();
+        let encoded = PngEncoder::new_with_quality(&mut png, compression, FilterType::Adaptive)
+            .write_image(
+                &raster::rgba(&pixmap),
+                pixmap.width(),
+                pixmap.height(),
+                ExtendedColorType::Rgba8,
+            );
+        if let Err(err) = encoded {
+            self.report(format!("failed to encode PNG: {err}"));
+            return Vec::new();
+        }
+
+        png
+    }
+
+    /// Compiles and rasterizes a single page, reporting if it doesn't exist
+    fn render_page(&mut self, index: usize, ppi: f32) -> Option<Pixmap> {
+        let doc = self.compile()?;
+        let Some(page) = doc.pages().get(index) else {
+            self.report(format!("page {index} doesn't exist"));
+            return None;
+        };
+
+        self.rasterize(page, ppi / 72.0)
+    }
+
+    /// Rasterizes a page at `scale` pixels per point, reporting sizes the renderer
+    /// can't allocate instead of letting it panic
+    fn rasterize(&mut self, page: &Page, scale: f32) -> Option<Pixmap> {
+        if !self.check_scale(scale) {
+            return None;
+        }
+
+        let (width, height) = raster::size(page.frame.size(), scale);
+        if !raster::fits(width, height) {
+            self.report(format!("{width}x{height} pixels are too large to render"));
+            return None;
+        }
+
+        Some(typst_render::render(page, scale))
+    }
+
+    /// Whether `scale` is a usable resolution, reporting it if not
+    fn check_scale(&mut self, scale: f32) -> bool {
+        let valid = scale.is_finite() && scale > 0.0;
+        if !valid {
+            self.report(format!(
+                "resolution must be positive, not {} ppi",
+                scale * 72.0
+            ));
+        }
+
+        valid
+    }
+
+    /// Finds a chain of imports from the main file that leads back to a file in it
+    fn import_cycle(&self) -> Option<Vec<String>> {
+        let files = self.files.loader();
+        let main = PathBuf::from(files.path(files.main));
+
+        find_cycle(files, main, &mut Vec::new(), &mut HashSet::new())
+    }
+
+    /// Compiles the document, storing errors or warnings in `self.errors`
+    ///
+    /// Reuses the last document if nothing changed since it was compiled
+    fn compile(&mut self) -> Option<Arc<PagedDocument>> {
+        let mut doc = self.compile_cached();
+        if doc.is_some() && self.provide_fonts() {
+            doc = self.compile_cached();
+        }
+        let doc = doc?;
+
+        if self.max_pages > 0 && doc.pages().len() > self.max_pages {
+            self.errors = EcoVec::new();
+            self.report(format!(
+                "document has {} pages, more than the limit of {}",
+                doc.pages().len(),
+                self.max_pages
+            ));
+            return None;
+        }
+
+        if self.memory_limit > 0 {
+            let estimate = self.memory_estimate(&doc);
+            if estimate > self.memory_limit {
+                self.errors = EcoVec::new();
+                self.report(format!(
+                    "document needs about {estimate} bytes of memory, more than the limit of {}",
+                    self.memory_limit
+                ));
+                return None;
+            }
+        }
+
+        if self.warnings_as_errors && !self.errors.is_empty() {
+            for err in self.errors.make_mut() {
+                err.severity = Severity::Error;
+            }
+            return None;
+        }
+
+        Some(doc)
+    }
+
+    /// The rough memory use of a document, see `set_memory_limit`
+    fn memory_estimate(&self, doc: &PagedDocument) -> usize {
+        let files: usize = self
+            .files
+            .loader()
+            .entries()
+            .iter()
+            .map(|(_, data)| data.len())
+            .sum();
+        let items: usize = doc
+            .pages()
+            .iter()
+            .map(|page| frames::item_count(&page.frame))
+            .sum();
+        let raster = doc
+            .pages()
+            .iter()
+            .map(|page| {
+                let size = page.frame.size();
+                (size.x.to_pt() * size.y.to_pt()).max(0.0) as usize * 4
+            })
+            .max()
+            .unwrap_or(0);
+
+        files + items * 256 + raster
+    }
+
+    fn compile_cached(&mut self) -> Option<Arc<PagedDocument>> {
+        if self.packages_version != fs::packages_version() {
+            self.packages_version = fs::packages_version();
+            self.invalidate();
+        }
+
+        if let Some((doc, warnings)) = &self.document {
+            self.errors = warnings.clone();
+            return Some(doc.clone());
+        }
+
+        self.accessed.lock().unwrap().clear();
+        self.files.loader().clear_missing_urls();
+        self.wrapper = self.build_wrapper();
+        let compiled = typst::compile::<PagedDocument>(self);
+
+        match compiled.output {
+            Err(errors) => {
+                self.errors = errors;
+                if self.errors.iter().any(|err| err.message.contains("cyclic"))
+                    && let Some(cycle) = self.import_cycle()
+                {
+                    self.report(format!("import cycle: {}", cycle.join(" -> ")));
+                }
+                None
+            }
+            Ok(doc) => {
+                let doc = Arc::new(doc);
+                self.errors = compiled.warnings.clone();
+                self.document = Some((doc.clone(), compiled.warnings));
+                Some(doc)
+            }
+        }
+    }
+}
+
+/// Depth-first search for an import cycle, skipping files already searched in `done`
+fn find_cycle(
+    files: &fs::FS,
+    path: PathBuf,
+    stack: &mut Vec<PathBuf>,
+    done: &mut HashSet<PathBuf>,
+) -> Option<Vec<String>> {
+    if let Some(start) = stack.iter().position(|visited| *visited == path) {
+        let cycle = stack[start..].iter().chain([&path]);
+        return Some(
+            cycle
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect(),
+        );
+    }
+    if done.contains(&path) {
+        return None;
+    }
+
+    let data = files.read(&path)?;
+    let text = std::str::from_utf8(&data).ok()?;
+
+    stack.push(path.clone());
+    for target in imports::targets(text) {
+        let next = imports::resolve(&path, &target, files.root());
+        if let Some(cycle) = find_cycle(files, next, stack, done) {
+            return Some(cycle);
+        }
+    }
+    stack.pop();
+    done.insert(path);
+
+    None
+}
+
+/// The font faces built into typ-js
+fn bundled_fonts() -> impl Iterator<Item = Font> {
+    typst_assets::fonts().flat_map(|data| Font::iter(Bytes::new(data)))
+}
+
+/// The current time in milliseconds, from `performance.now()` if available
+fn now() -> f64 {
+    Reflect::get(&js_sys::global(), &"performance".into())
+        .and_then(|performance| {
+            let now: Function = Reflect::get(&performance, &"now".into())?.dyn_into()?;
+            now.call0(&performance)
+        })
+        .ok()
+        .and_then(|time| time.as_f64())
+        .unwrap_or_else(js_sys::Date::now)
+}
+
+/// Extensions of files that typst reads as text
+const TEXT_EXTENSIONS: &[&str] = &[
+    "typ", "bib", "csv", "json", "toml", "txt", "xml", "yaml", "yml",
+];
+
+/// The padding around cropped output, 0 if omitted or not a positive number of points
+fn padding(padding_pt: Option<f64>) -> Abs {
+    Abs::pt(padding_pt.filter(|pt| *pt > 0.0).unwrap_or(0.0))
+}
+
+/// Extensions of files typst can use as images
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "svg", "webp", "pdf"];
+
+fn is_text(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| TEXT_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// The single top-level folder that contains all the given paths, if any
+fn common_folder(paths: &[PathBuf]) -> Option<PathBuf> {
+    let first = paths.first()?.components().next()?;
+
+    paths
+        .iter()
+        .all(|path| path.components().count() > 1 && path.components().next() == Some(first))
+        .then(|| PathBuf::from(first.as_os_str()))
+}
+
+impl World for TypJs {
+    fn library(&self) -> &LazyHash<Library> {
+        &self.lib
+    }
+
+    fn book(&self) -> &LazyHash<FontBook> {
+        &self.book
+    }
+
+    fn main(&self) -> FileId {
+        match &self.wrapper {
+            Some(wrapper) => wrapper.id(),
+            None => self.files.loader().main,
+        }
+    }
+
+    fn source(&self, id: FileId) -> typst::diag::FileResult<Source> {
+        if let Some(wrapper) = self.wrapper.as_ref().filter(|wrapper| wrapper.id() == id) {
+            return Ok(wrapper.clone());
+        }
+
+        let source = self.files.source(id)?;
+        self.accessed.lock().unwrap().insert(id);
+
+        Ok(source)
+    }
+
+    fn file(&self, id: FileId) -> typst::diag::FileResult<Bytes> {
+        let file = self.files.file(id)?;
+        self.accessed.lock().unwrap().insert(id);
+
+        Ok(file)
+    }
+
+    fn font(&self, index: usize) -> Option<Font> {
+        self.fonts.get(index).cloned()
+    }
+
+    fn today(&self, offset: Option<typst::foundations::Duration>) -> Option<Datetime> {
+        if self.deterministic {
+            return Some(Self::epoch());
+        }
+
+        self.now.today(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut archive = zip::Writer::new();
+        for (path, data) in files {
+            archive.add(path, data).unwrap();
+        }
+
+        archive.finish().unwrap()
+    }
+
+    #[test]
+    fn zip_round_trips_text_and_binary_files() {
+        let mut typ = TypJs::new();
+        typ.write("main.typ", "= Hello".into());
+        typ.attach("logo.bin", vec![0, 159, 146, 150, 255]);
+
+        let mut loaded = TypJs::new();
+        loaded.load_zip(typ.export_zip());
+
+        assert!(loaded.errors().is_empty());
+        let files = loaded.files.loader();
+        assert_eq!(
+            files.read(Path::new("main.typ")).as_deref(),
+            Some(&b"= Hello"[..])
+        );
+        assert_eq!(
+            files.read(Path::new("logo.bin")).as_deref(),
+            Some(&[0, 159, 146, 150, 255][..])
+        );
+    }
+
+    #[test]
+    fn zip_with_one_top_level_folder_is_unpacked_into_the_root() {
+        let mut typ = TypJs::new();
+        typ.load_zip(zip_of(&[
+            ("project/main.typ", b"= Hi"),
+            ("project/lib/util.typ", b"#let x = 1"),
+        ]));
+
+        assert!(typ.errors().is_empty());
+        let mut files = typ.list();
+        files.sort();
+        assert_eq!(files, ["lib/util.typ", "main.typ"]);
+        assert_eq!(
+            typ.files.loader().read(Path::new("main.typ")).as_deref(),
+            Some(&b"= Hi"[..])
+        );
+    }
+
+    #[test]
+    fn zip_entries_leaving_the_root_are_skipped() {
+        let mut typ = TypJs::new();
+        typ.load_zip(zip_of(&[("main.typ", b"= Hi"), ("../evil.typ", b"x")]));
+
+        assert_eq!(typ.errors().len(), 1);
+        assert!(typ.errors()[0].contains("../evil.typ: path leaves the project root"));
+        assert_eq!(typ.list(), ["main.typ"]);
+    }
+
+    #[test]
+    fn main_files_leaving_the_root_are_reported() {
+        let mut typ = TypJs::new();
+        typ.set_main("../x.typ");
+
+        assert!(typ.errors()[0].contains("../x.typ: path leaves the project root"));
+        assert_eq!(typ.get_main(), "main.typ");
+        assert!(!typ.is_source("../x.typ"));
+    }
+
+    #[test]
+    fn roots_leaving_the_project_are_reported() {
+        let mut typ = TypJs::new();
+        typ.write("main.typ", "= Hi".into());
+        typ.set_root("../x");
+
+        assert!(typ.errors()[0].contains("../x: path leaves the project root"));
+        assert_eq!(typ.get_main(), "main.typ");
+        assert!(!typ.svg().is_empty());
+    }
+
+    #[test]
+    fn attached_copies_of_bundled_fonts_are_user_fonts() {
+        let mut typ = TypJs::new();
+        assert!(typ.user_fonts().is_empty());
+
+        let data = typst_assets::fonts().next().unwrap();
+        assert!(typ.attach_font("fonts/copy.ttf", data.to_vec(), None) > 0);
+        assert!(!typ.user_fonts().is_empty());
+    }
+
+    #[test]
+    fn pdf_producers_longer_than_the_default_are_appended() {
+        let contains = |pdf: &[u8], text: &[u8]| pdf.windows(text.len()).any(|w| w == text);
+
+        let mut typ = TypJs::new();
+        typ.set_pdf_producer(Some("Acme Publishing Platform (Enterprise)".into()));
+        let pdf = typ.pdf();
+
+        assert!(typ.errors().is_empty(), "{:?}", typ.errors());
+        assert!(contains(
+            &pdf,
+            b"/Producer (Acme Publishing Platform \\(Enterprise\\))"
+        ));
+        assert!(!contains(&pdf, b"(Typst "));
+        assert!(pdf.ends_with(b"%%EOF\n"));
+    }
+
+    #[test]
+    fn import_cycles_are_reported_as_a_chain() {
+        let mut files = fs::FS::new();
+        files.write(Path::new("main.typ"), Bytes::new("#import \"a.typ\": *"));
+        files.write(Path::new("a.typ"), Bytes::new("#import \"b.typ\": *"));
+        files.write(Path::new("b.typ"), Bytes::new("#import \"a.typ\": *"));
+
+        let main = PathBuf::from(files.path(files.main));
+        let cycle = find_cycle(&files, main, &mut Vec::new(), &mut HashSet::new());
+
+        assert_eq!(
+            cycle,
+            Some(vec!["a.typ".into(), "b.typ".into(), "a.typ".into()])
+        );
+    }
+
+    #[test]
+    fn absolute_imports_resolve_inside_the_root() {
+        let mut typ = TypJs::new();
+        typ.write(
+            "src/main.typ",
+            "#import \"/lib/util.typ\": x\n#import \"parts/intro.typ\": y\n#x #y".into(),
+        );
+        typ.write("src/lib/util.typ", "#let x = 1".into());
+        typ.write("src/parts/intro.typ", "#let y = 2".into());
+        typ.set_root("src");
+        typ.set_main("src/main.typ");
+
+        assert!(!typ.svg().is_empty());
+        assert!(typ.errors().is_empty(), "{:?}", typ.errors());
+    }
+
+    #[test]
+    fn compiling_an_import_cycle_reports_the_chain() {
+        let mut typ = TypJs::new();
+        typ.write("main.typ", "#import \"a.typ\": *".into());
+        typ.write("a.typ", "#import \"b.typ\": *".into());
+        typ.write("b.typ", "#import \"a.typ\": *".into());
+
+        assert!(typ.svg().is_empty());
+        assert!(
+            typ.errors()
+                .iter()
+                .any(|err| err.contains("import cycle: a.typ -> b.typ -> a.typ")),
+            "{:?}",
+            typ.errors()
+        );
+    }
+
+    /// The height in the `viewBox` of the outermost `<svg>`
+    fn svg_height(svg: &str) -> f64 {
+        let start = svg.find("viewBox=\"").unwrap() + "viewBox=\"".len();
+        let view_box = &svg[start..start + svg[start..].find('"').unwrap()];
+
+        view_box.split(' ').nth(3).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn display_math_is_taller_than_inline_math() {
+        let mut typ = TypJs::new();
+        let inline = typ.render_math("sum_(i=1)^n i", false);
+        let inline_baseline = typ.last_baseline();
+        let display = typ.render_math("sum_(i=1)^n i", true);
+
+        assert!(typ.errors().is_empty(), "{:?}", typ.errors());
+        assert!(svg_height(&display) > svg_height(&inline));
+        assert!(typ.last_baseline() > inline_baseline);
     }
 }